@@ -0,0 +1,77 @@
+//! Sharing the machine's CPUs between several managed processes.
+
+use {Error, HcbResult};
+
+/// Tracks which CPUs each profile's hot threads have claimed, so no two profiles use the same
+/// CPU.
+#[derive(Debug, Clone, Default)]
+pub struct CoreAllocator {
+    claims: Vec<(String, usize)>,
+}
+
+impl CoreAllocator {
+    pub fn new() -> CoreAllocator {
+        CoreAllocator::default()
+    }
+
+    /// Returns the CPUs which no profile other than `profile` has claimed.
+    pub fn available(&self, profile: &str) -> usize {
+        !self.claims
+            .iter()
+            .filter(|&(name, _)| name != profile)
+            .fold(0, |claimed, &(_, cpus)| claimed | cpus)
+    }
+
+    /// Returns the CPUs `profile` has claimed.
+    pub fn claimed(&self, profile: &str) -> usize {
+        self.claims
+            .iter()
+            .find(|&(name, _)| name == profile)
+            .map_or(0, |&(_, cpus)| cpus)
+    }
+
+    /// Claims `cpus` for `profile`, replacing its previous claim.
+    ///
+    /// Fails without changing anything if another profile has claimed any of them.
+    pub fn claim(&mut self, profile: &str, cpus: usize) -> HcbResult<()> {
+        if let Some((name, overlap)) = self.claims
+            .iter()
+            .filter(|&(name, _)| name != profile)
+            .map(|(name, claimed)| (name, claimed & cpus))
+            .find(|&(_, overlap)| overlap != 0)
+        {
+            return Err(Error::Policy(format!(
+                "CPUs {:#x} are already claimed by profile '{}'",
+                overlap, name
+            )));
+        }
+        self.release(profile);
+        self.claims.push((profile.to_owned(), cpus));
+        Ok(())
+    }
+
+    /// Gives up every CPU `profile` has claimed.
+    pub fn release(&mut self, profile: &str) {
+        self.claims.retain(|(name, _)| name != profile);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_profiles_apart() {
+        let mut allocator = CoreAllocator::new();
+        allocator.claim("game", 0b1010).unwrap();
+        assert_eq!(allocator.available("encoder") & 0xff, 0b1111_0101);
+        assert!(allocator.claim("encoder", 0b0110).is_err());
+        assert_eq!(allocator.claimed("encoder"), 0);
+        allocator.claim("encoder", 0b0101).unwrap();
+        allocator.claim("game", 0b1000).unwrap();
+        assert_eq!(allocator.available("encoder") & 0xff, 0b1111_0111);
+        allocator.release("game");
+        assert_eq!(allocator.available("encoder"), !0);
+        assert_eq!(allocator.claimed("encoder"), 0b0101);
+    }
+}
@@ -0,0 +1,140 @@
+//! Platform-neutral access to processes and their threads.
+//!
+//! Everything above this module is written against these traits, so the monitoring and
+//! assignment logic does not depend on any particular operating system.
+
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+use topology::Topology;
+use {Error, HcbResult};
+
+/// Cumulative time a logical CPU has spent busy and in total, in backend specific units.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct CpuTime {
+    pub busy: u64,
+    pub total: u64,
+}
+
+/// An operating system's process and thread API.
+pub trait Backend {
+    type Process: OsProcess;
+
+    /// Enumerates all running processes which could be opened.
+    fn processes(&self) -> HcbResult<Vec<Self::Process>>;
+
+    /// Opens the running process with the given id.
+    ///
+    /// By default the process is looked for among all running processes.
+    fn process(&self, id: u32) -> HcbResult<Self::Process> {
+        self.processes()?
+            .into_iter()
+            .find(|p| p.id() == id)
+            .ok_or(Error::ProcessExited(id))
+    }
+
+    /// Returns the cumulative time of each logical CPU, indexed by CPU id.
+    ///
+    /// Backends which cannot measure this return an empty list.
+    fn cpu_times(&self) -> HcbResult<Vec<CpuTime>> {
+        Ok(Vec::new())
+    }
+
+    /// Discovers how the logical CPUs map onto cores, caches and packages.
+    ///
+    /// Backends which cannot discover this return `None`.
+    fn topology(&self) -> HcbResult<Option<Topology>> {
+        Ok(None)
+    }
+}
+
+/// A handle to a running process.
+pub trait OsProcess: Debug {
+    type Thread: OsThread;
+
+    /// Returns the process's id.
+    fn id(&self) -> u32;
+
+    /// Returns true if the process is running.
+    fn running(&self) -> bool;
+
+    /// Returns the unqualified name of the executable of the process.
+    fn name(&self) -> HcbResult<String>;
+
+    /// Returns the full path of the executable of the process.
+    fn path(&self) -> HcbResult<PathBuf>;
+
+    /// Returns the command line of the process, with arguments separated by spaces.
+    fn command_line(&self) -> HcbResult<String>;
+
+    /// Returns the id of the process's parent, or 0 if it has none.
+    fn parent_id(&self) -> HcbResult<u32>;
+
+    /// Returns when the process started, in backend specific units.
+    ///
+    /// Start times are only comparable within one backend. A process cannot have started before
+    /// its parent, which tells a parent apart from a later process which reused its id.
+    fn start_time(&self) -> HcbResult<u64>;
+
+    /// Returns who the process belongs to, in backend specific units, or `None` if it belongs
+    /// to the operating system itself.
+    ///
+    /// The system-wide reservation only moves processes with the same owner as the target.
+    fn owner(&self) -> HcbResult<Option<u32>>;
+
+    /// Returns the value of an environment variable of the process, as it was when the process
+    /// started.
+    ///
+    /// Backends which cannot read another process's environment return `None`.
+    fn environment_variable(&self, _name: &str) -> HcbResult<Option<String>> {
+        Ok(None)
+    }
+
+    /// Returns the affinity mask of the process.
+    fn affinity_mask(&self) -> HcbResult<usize>;
+
+    /// Sets the affinity of the process and all of its threads.
+    /// On success, returns the previous affinity mask.
+    fn set_affinity_mask(&mut self, mask: usize) -> HcbResult<usize>;
+
+    /// Returns the ids of the threads currently belonging to the process.
+    fn thread_ids(&self) -> HcbResult<Vec<u32>>;
+
+    /// Opens one of the process's threads by id.
+    fn thread(&self, id: u32) -> HcbResult<Self::Thread>;
+}
+
+/// A handle to a thread of a running process.
+pub trait OsThread: Debug {
+    /// Returns the thread's id.
+    fn id(&self) -> u32;
+
+    /// Returns the name the thread was given, if the backend can read it.
+    fn name(&self) -> HcbResult<Option<String>> {
+        Ok(None)
+    }
+
+    /// Returns a monotonically increasing count of the CPU time used by the thread.
+    ///
+    /// The unit is backend specific, so counters are only comparable within one backend.
+    fn cpu_counter(&self) -> HcbResult<u64>;
+
+    /// Gets the preferred processor for the thread.
+    fn ideal_processor(&self) -> HcbResult<u32>;
+
+    /// Sets the preferred processor for the thread.
+    /// On success, returns the previous ideal processor.
+    fn set_ideal_processor(&mut self, processor: u32) -> HcbResult<u32>;
+
+    /// Returns true if the backend emulates the ideal processor with the affinity mask, so that
+    /// it is only stable while the thread is pinned to one CPU.
+    fn emulates_ideal_processor(&self) -> bool {
+        false
+    }
+
+    /// Returns the affinity mask of the thread.
+    fn affinity_mask(&self) -> HcbResult<usize>;
+
+    /// Sets the affinity of the thread. On success, returns the previous affinity mask.
+    fn set_affinity_mask(&mut self, mask: usize) -> HcbResult<usize>;
+}
@@ -0,0 +1,258 @@
+//! The configuration file, which describes one or more named profiles.
+//!
+//! Settings at the top level apply to every profile, and each `[profiles.<name>]` table adds or
+//! overrides settings for one profile. Keys are the same as those accepted by `Settings::set`,
+//! apart from `log`, which sets the logging level. Keys which build a list, such as `rule`, take
+//! an array with one entry per element:
+//!
+//! ```toml
+//! log = "info"
+//! poll = 1
+//!
+//! [profiles.game]
+//! target = "RocketLeague.exe"
+//! threads = 3
+//! rule = ["name:RenderThread* -> 4", "share<1% -> efficiency"]
+//!
+//! [profiles.encoder]
+//! target = "obs64.exe"
+//! threads = 2
+//! cores = "8-11"
+//! ```
+//!
+//! Without a `profiles` table the top-level settings make up a single profile named `default`.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use dirs;
+use toml::Value;
+
+use settings::{self, Settings};
+use {Error, HcbResult};
+
+/// The logging levels the `log` key accepts.
+const LOG_LEVELS: &[&str] = &["off", "error", "warn", "info", "debug", "trace"];
+
+/// The settings read from a configuration file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// The logging level, if the file sets one.
+    pub log: Option<String>,
+    /// The profiles, ordered by name.
+    pub profiles: Vec<Settings>,
+}
+
+impl Default for Config {
+    /// A single default profile.
+    fn default() -> Config {
+        Config {
+            log: None,
+            profiles: vec![Settings::default()],
+        }
+    }
+}
+
+/// Returns where the configuration file is looked for by default, such as
+/// `~/.config/rlhcbfix/config.toml` on Linux.
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("rlhcbfix").join("config.toml"))
+}
+
+/// Converts a value to the textual form `Settings::set` accepts, joining arrays with commas.
+fn text(value: &Value) -> Option<String> {
+    match *value {
+        Value::String(ref s) => Some(s.clone()),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Float(f) => Some(f.to_string()),
+        Value::Boolean(b) => Some(b.to_string()),
+        Value::Array(ref values) => {
+            let parts: Option<Vec<String>> = values.iter().map(text).collect();
+            parts.map(|parts| parts.join(","))
+        }
+        _ => None,
+    }
+}
+
+/// Applies every setting in `table` except `skip`, recording each invalid one in `errors`.
+fn apply(
+    settings: &mut Settings,
+    table: &::toml::value::Table,
+    prefix: &str,
+    skip: &[&str],
+    errors: &mut Vec<String>,
+) {
+    for (key, value) in table.iter().filter(|&(key, _)| !skip.contains(&key.as_str())) {
+        let values = match *value {
+            Value::Array(ref values) if settings::LIST_KEYS.contains(&key.as_str()) => {
+                values.iter().collect()
+            }
+            _ => vec![value],
+        };
+        let result = values.into_iter().try_for_each(|value| match text(value) {
+            Some(value) => settings.set(key, &value),
+            None => Err(Error::Config(format!("Unsupported value {} for {}", value, key))),
+        });
+        match result {
+            Err(Error::Config(reason)) => errors.push(format!("{}{}: {}", prefix, key, reason)),
+            Err(e) => errors.push(format!("{}{}: {}", prefix, key, e)),
+            Ok(()) => {}
+        }
+    }
+}
+
+impl Config {
+    /// Parses a configuration file's contents.
+    ///
+    /// Every invalid key is reported at once, in a single configuration error.
+    pub fn parse(contents: &str) -> HcbResult<Config> {
+        let root: Value = contents
+            .parse()
+            .map_err(|e| Error::Config(format!("Invalid configuration file: {}", e)))?;
+        let root = match root {
+            Value::Table(table) => table,
+            _ => return Err(Error::Config("Invalid configuration file".to_owned())),
+        };
+        let mut errors = Vec::new();
+        let log = match root.get("log") {
+            None => None,
+            Some(Value::String(level)) if LOG_LEVELS.contains(&level.as_str()) => {
+                Some(level.clone())
+            }
+            Some(value) => {
+                errors.push(format!(
+                    "log: Invalid value {}, expected one of {}",
+                    value,
+                    LOG_LEVELS.join(", ")
+                ));
+                None
+            }
+        };
+        let mut defaults = Settings::default();
+        apply(&mut defaults, &root, "", &["log", "profiles"], &mut errors);
+        let profiles = match root.get("profiles") {
+            None => vec![defaults],
+            Some(Value::Table(tables)) => {
+                let mut profiles = Vec::new();
+                for (name, table) in tables {
+                    let prefix = format!("profiles.{}.", name);
+                    let mut profile = Settings {
+                        name: name.clone(),
+                        ..defaults.clone()
+                    };
+                    match *table {
+                        Value::Table(ref table) => {
+                            apply(&mut profile, table, &prefix, &[], &mut errors)
+                        }
+                        _ => errors.push(format!("profiles.{}: Expected a table", name)),
+                    }
+                    profiles.push(profile);
+                }
+                if profiles.is_empty() {
+                    errors.push("profiles: No profiles are defined".to_owned());
+                }
+                profiles
+            }
+            Some(_) => {
+                errors.push("profiles: Expected a table of profiles".to_owned());
+                Vec::new()
+            }
+        };
+        if !errors.is_empty() {
+            return Err(Error::Config(format!(
+                "Invalid configuration:\n  {}",
+                errors.join("\n  ")
+            )));
+        }
+        Ok(Config { log, profiles })
+    }
+
+    /// Reads and parses the configuration file at `path`.
+    pub fn load(path: &Path) -> HcbResult<Config> {
+        let contents = fs::read_to_string(path)?;
+        Config::parse(&contents).map_err(|e| match e {
+            Error::Config(reason) => Error::Config(format!("{}: {}", path.display(), reason)),
+            e => e,
+        })
+    }
+
+    /// Reads the configuration file at the default path, or returns the default configuration
+    /// if there is none.
+    pub fn load_default() -> HcbResult<Config> {
+        match default_path() {
+            Some(path) => match Config::load(&path) {
+                Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+                    Ok(Config::default())
+                }
+                result => result,
+            },
+            None => Ok(Config::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use selector::Selector;
+    use std::time::Duration;
+
+    #[test]
+    fn reads_named_profiles_over_shared_settings() {
+        let config = Config::parse(
+            r#"
+            log = "debug"
+            poll = 2
+            threads = 4
+
+            [profiles.game]
+            target = "glob:Rocket*"
+            mode = "hard"
+
+            [profiles.encoder]
+            target = "obs64.exe"
+            threads = 2
+            cores = [8, 9, 10, 11]
+            rule = ["name:x264_* -> 8,9", "rank:1 -> 10"]
+            "#,
+        ).unwrap();
+        assert_eq!(config.log, Some("debug".to_owned()));
+        let names: Vec<&str> = config.profiles.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["encoder", "game"]);
+        let (encoder, game) = (&config.profiles[0], &config.profiles[1]);
+        assert_eq!(encoder.hot_threads, 2);
+        assert_eq!(encoder.cores, 0xf00);
+        assert_eq!(encoder.poll_interval, Duration::from_secs(2));
+        assert_eq!(encoder.rules.len(), 2);
+        assert_eq!(encoder.rules[0].to_string(), "name:x264_* -> 8,9");
+        assert_eq!(game.target, "glob:Rocket*".parse::<Selector>().unwrap());
+        assert_eq!(game.hot_threads, 4);
+    }
+
+    #[test]
+    fn uses_the_top_level_as_the_default_profile() {
+        let config = Config::parse("settle = 5").unwrap();
+        assert_eq!(config.profiles.len(), 1);
+        assert_eq!(config.profiles[0].name, "default");
+        assert_eq!(config.profiles[0].settling_period, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn lists_every_invalid_key() {
+        let error = Config::parse(
+            r#"
+            log = "loud"
+            colour = "blue"
+
+            [profiles.game]
+            threads = "three"
+            policy = "fastest"
+            "#,
+        ).unwrap_err()
+            .to_string();
+        for key in &["log", "colour", "profiles.game.threads", "profiles.game.policy"] {
+            assert!(error.contains(&format!("\n  {}: ", key)), "{} missing from {}", key, error);
+        }
+    }
+}
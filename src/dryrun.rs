@@ -0,0 +1,226 @@
+//! A backend decorator which reports placement changes instead of making them.
+//!
+//! Reads are passed through, except that a thread reports the ideal processor or affinity mask it
+//! would have been given for as long as the real value stays what it was when the change was
+//! skipped. If something else moves the thread, the real value shows through again, so the
+//! manager still notices and reports the correction it would make. Where the backend emulates
+//! the ideal processor with the affinity mask, the pretend ideal processor lasts as long as the
+//! real affinity mask instead, since an unpinned thread's emulated ideal processor follows the
+//! scheduler.
+
+use std::cell::Cell;
+use std::path::PathBuf;
+
+use backend::{Backend, CpuTime, OsProcess, OsThread};
+use topology::Topology;
+use HcbResult;
+
+/// Wraps a backend so that nothing is changed, only logged.
+#[derive(Debug, Clone, Default)]
+pub struct DryRun<B> {
+    backend: B,
+}
+
+impl<B: Backend> DryRun<B> {
+    pub fn new(backend: B) -> DryRun<B> {
+        DryRun { backend }
+    }
+}
+
+impl<B: Backend> Backend for DryRun<B> {
+    type Process = DryRunProcess<B::Process>;
+
+    fn processes(&self) -> HcbResult<Vec<Self::Process>> {
+        Ok(self.backend
+            .processes()?
+            .into_iter()
+            .map(|process| DryRunProcess {
+                process,
+                affinity: Cell::new(None),
+            })
+            .collect())
+    }
+
+    fn process(&self, id: u32) -> HcbResult<Self::Process> {
+        Ok(DryRunProcess {
+            process: self.backend.process(id)?,
+            affinity: Cell::new(None),
+        })
+    }
+
+    fn cpu_times(&self) -> HcbResult<Vec<CpuTime>> {
+        self.backend.cpu_times()
+    }
+
+    fn topology(&self) -> HcbResult<Option<Topology>> {
+        self.backend.topology()
+    }
+}
+
+/// A value which would have been set, and the real value it is compared against at the time.
+type Pretend<T, W = T> = Cell<Option<(T, W)>>;
+
+/// Returns the pretend value if the real one has not changed since it was skipped.
+fn overlay<T: Copy + PartialEq>(pretend: &Pretend<T>, real: T) -> T {
+    overlay_while(pretend, real, real)
+}
+
+/// Returns the pretend value if `witness` has not changed since it was skipped, and otherwise
+/// the real value.
+fn overlay_while<T: Copy, W: Copy + PartialEq>(
+    pretend: &Pretend<T, W>,
+    real: T,
+    witness: W,
+) -> T {
+    match pretend.get() {
+        Some((value, then)) if then == witness => value,
+        _ => {
+            pretend.set(None);
+            real
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DryRunProcess<P> {
+    process: P,
+    affinity: Pretend<usize>,
+}
+
+impl<P: OsProcess> OsProcess for DryRunProcess<P> {
+    type Thread = DryRunThread<P::Thread>;
+
+    fn id(&self) -> u32 {
+        self.process.id()
+    }
+
+    fn running(&self) -> bool {
+        self.process.running()
+    }
+
+    fn name(&self) -> HcbResult<String> {
+        self.process.name()
+    }
+
+    fn path(&self) -> HcbResult<PathBuf> {
+        self.process.path()
+    }
+
+    fn command_line(&self) -> HcbResult<String> {
+        self.process.command_line()
+    }
+
+    fn parent_id(&self) -> HcbResult<u32> {
+        self.process.parent_id()
+    }
+
+    fn start_time(&self) -> HcbResult<u64> {
+        self.process.start_time()
+    }
+
+    fn owner(&self) -> HcbResult<Option<u32>> {
+        self.process.owner()
+    }
+
+    fn environment_variable(&self, name: &str) -> HcbResult<Option<String>> {
+        self.process.environment_variable(name)
+    }
+
+    fn affinity_mask(&self) -> HcbResult<usize> {
+        Ok(overlay(&self.affinity, self.process.affinity_mask()?))
+    }
+
+    fn set_affinity_mask(&mut self, mask: usize) -> HcbResult<usize> {
+        let real = self.process.affinity_mask()?;
+        let prev = overlay(&self.affinity, real);
+        info!(
+            "Dry run: would set the affinity of process {} to {:#x}.",
+            self.id(),
+            mask
+        );
+        self.affinity.set(Some((mask, real)));
+        Ok(prev)
+    }
+
+    fn thread_ids(&self) -> HcbResult<Vec<u32>> {
+        self.process.thread_ids()
+    }
+
+    fn thread(&self, id: u32) -> HcbResult<Self::Thread> {
+        Ok(DryRunThread {
+            thread: self.process.thread(id)?,
+            ideal: Cell::new(None),
+            affinity: Cell::new(None),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct DryRunThread<T> {
+    thread: T,
+    ideal: Pretend<u32, usize>,
+    affinity: Pretend<usize>,
+}
+
+impl<T: OsThread> DryRunThread<T> {
+    /// Returns what shows whether the thread's real ideal processor `real` has been moved.
+    fn ideal_witness(&self, real: u32) -> HcbResult<usize> {
+        if self.thread.emulates_ideal_processor() {
+            self.thread.affinity_mask()
+        } else {
+            Ok(real as usize)
+        }
+    }
+}
+
+impl<T: OsThread> OsThread for DryRunThread<T> {
+    fn id(&self) -> u32 {
+        self.thread.id()
+    }
+
+    fn name(&self) -> HcbResult<Option<String>> {
+        self.thread.name()
+    }
+
+    fn cpu_counter(&self) -> HcbResult<u64> {
+        self.thread.cpu_counter()
+    }
+
+    fn ideal_processor(&self) -> HcbResult<u32> {
+        let real = self.thread.ideal_processor()?;
+        Ok(overlay_while(&self.ideal, real, self.ideal_witness(real)?))
+    }
+
+    fn set_ideal_processor(&mut self, processor: u32) -> HcbResult<u32> {
+        let real = self.thread.ideal_processor()?;
+        let witness = self.ideal_witness(real)?;
+        let prev = overlay_while(&self.ideal, real, witness);
+        info!(
+            "Dry run: would set the ideal processor of thread {} to {}.",
+            self.id(),
+            processor
+        );
+        self.ideal.set(Some((processor, witness)));
+        Ok(prev)
+    }
+
+    fn emulates_ideal_processor(&self) -> bool {
+        self.thread.emulates_ideal_processor()
+    }
+
+    fn affinity_mask(&self) -> HcbResult<usize> {
+        Ok(overlay(&self.affinity, self.thread.affinity_mask()?))
+    }
+
+    fn set_affinity_mask(&mut self, mask: usize) -> HcbResult<usize> {
+        let real = self.thread.affinity_mask()?;
+        let prev = overlay(&self.affinity, real);
+        info!(
+            "Dry run: would set the affinity of thread {} to {:#x}.",
+            self.id(),
+            mask
+        );
+        self.affinity.set(Some((mask, real)));
+        Ok(prev)
+    }
+}
@@ -1,17 +1,38 @@
+use std::io;
+
+#[cfg(windows)]
 use win;
 
 #[derive(Debug, Fail)]
 pub enum Error {
+    #[cfg(windows)]
     #[fail(display = "Windows error: {:?}", _0)]
     Windows(#[cause] win::Error),
-    #[fail(display = "No Rocket League process found.")]
-    NoProcess,
+    #[fail(display = "I/O error: {}", _0)]
+    Io(#[cause] io::Error),
+    #[fail(display = "No process matching '{}' found.", _0)]
+    NoProcess(String),
+    #[fail(display = "Process {} exited.", _0)]
+    ProcessExited(u32),
+    #[fail(display = "Cannot manage {} hot threads on {} usable CPUs.", requested, available)]
+    HotThreads { requested: usize, available: u32 },
+    #[fail(display = "{}.", _0)]
+    Policy(String),
+    #[fail(display = "{}.", _0)]
+    Config(String),
 }
 
 pub type HcbResult<T> = ::std::result::Result<T, Error>;
 
+#[cfg(windows)]
 impl From<win::Error> for Error {
     fn from(err: win::Error) -> Error {
         Error::Windows(err)
     }
 }
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
@@ -0,0 +1,84 @@
+//! Keeping a process's other threads off the CPUs reserved for its hot threads.
+
+use std::collections::HashMap;
+
+use backend::{OsProcess, OsThread};
+use procext::MonitoredProcess;
+use {Error, HcbResult};
+
+#[derive(Debug, Clone)]
+pub struct Evictor {
+    /// The process's affinity mask.
+    allowed: usize,
+    /// The mask given to evicted threads.
+    mask: usize,
+    /// The mask each evicted thread was last given.
+    evicted: HashMap<u32, usize>,
+}
+
+impl Evictor {
+    /// Creates an evictor for a process with the affinity mask `allowed`, reserving nothing.
+    pub fn new(allowed: usize) -> Evictor {
+        Evictor {
+            allowed,
+            mask: allowed,
+            evicted: HashMap::new(),
+        }
+    }
+
+    /// Returns the mask other threads are restricted to.
+    pub fn mask(&self) -> usize {
+        self.mask
+    }
+
+    /// Reserves the CPUs in `reserved` for the hot threads.
+    pub fn reserve(&mut self, reserved: usize) -> HcbResult<()> {
+        let mask = self.allowed & !reserved;
+        if mask == 0 {
+            return Err(Error::Policy(format!(
+                "Reserving CPUs {:#x} leaves no CPUs for the process's other threads",
+                reserved
+            )));
+        }
+        self.mask = mask;
+        Ok(())
+    }
+
+    /// Gives a thread back the process's full affinity mask if it was evicted.
+    pub fn release<T: OsThread>(&mut self, thread: &mut T) -> HcbResult<()> {
+        if self.evicted.remove(&thread.id()).is_some() {
+            thread.set_affinity_mask(self.allowed)?;
+        }
+        Ok(())
+    }
+
+    /// Forgets that a thread was evicted, such as after its original placement was restored, so
+    /// the next update evicts it again.
+    pub fn forget(&mut self, id: u32) {
+        self.evicted.remove(&id);
+    }
+
+    /// Restricts every thread of the process apart from `hot` to the unreserved CPUs, skipping
+    /// threads which already are.
+    ///
+    /// Threads which exit before they can be moved are ignored.
+    pub fn update<P: OsProcess>(&mut self, process: &mut MonitoredProcess<P>, hot: &[u32]) {
+        let mask = self.mask;
+        let threads = process.threads_mut();
+        self.evicted.retain(|id, _| threads.contains_key(id));
+        let mut ids: Vec<u32> = threads
+            .keys()
+            .filter(|id| !hot.contains(id) && self.evicted.get(id) != Some(&mask))
+            .cloned()
+            .collect();
+        ids.sort_unstable();
+        for id in ids {
+            match threads.get_mut(&id).unwrap().set_affinity_mask(mask) {
+                Ok(_) => {
+                    self.evicted.insert(id, mask);
+                }
+                Err(e) => debug!("Could not evict thread {}: {}", id, e),
+            }
+        }
+    }
+}
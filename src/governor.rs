@@ -0,0 +1,209 @@
+//! The settling state machine behind the thread manager.
+//!
+//! A [`Governor`](struct.Governor.html) is fed one [`Snapshot`](struct.Snapshot.html) of the
+//! hottest threads per poll, together with the time it was taken, and answers with the
+//! [`Decision`](enum.Decision.html)s the caller should act on. It performs no I/O and never reads
+//! the clock itself, so it can be driven tick-by-tick.
+
+use std::time::{Duration, Instant};
+
+/// The state of the hottest threads at one poll.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    /// The ids of the most active threads, sorted.
+    pub hot: Vec<u32>,
+    /// Whether those threads are currently on the cores they would be assigned.
+    pub in_place: bool,
+}
+
+/// What the caller should do in response to a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Nothing to do until the next poll.
+    Wait,
+    /// The hot threads changed, restarting the settling period.
+    Changed(Vec<u32>),
+    /// The hot threads changed back to the ones which were last assigned.
+    Returned(Vec<u32>),
+    /// The hot threads have nearly settled and will be assigned after `remaining` if unchanged.
+    AnnounceSettling { remaining: Duration },
+    /// The hot threads have settled and should be assigned to their cores.
+    Assign(Vec<u32>),
+    /// The assigned threads have left their cores and should be assigned again.
+    Correct(Vec<u32>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Governor {
+    settling_period: Duration,
+    /// The threads which have had affinity assigned.
+    assigned: Option<Vec<u32>>,
+    /// The hot threads at the last step.
+    hot: Option<Vec<u32>>,
+    /// When the hot threads last changed.
+    last_changed: Option<Instant>,
+    /// Whether the imminent assignment has been announced.
+    announced: bool,
+    /// Whether the current hot threads equal the ones with assigned affinities.
+    stable: bool,
+}
+
+impl Governor {
+    pub fn new(settling_period: Duration) -> Governor {
+        Governor {
+            settling_period,
+            assigned: None,
+            hot: None,
+            last_changed: None,
+            announced: false,
+            stable: false,
+        }
+    }
+
+    /// Returns the threads which were last assigned, if any.
+    pub fn assigned(&self) -> Option<&[u32]> {
+        self.assigned.as_ref().map(|assigned| &assigned[..])
+    }
+
+    /// Changes how long the hot threads must stay the same, from the next step on.
+    pub fn set_settling_period(&mut self, settling_period: Duration) {
+        self.settling_period = settling_period;
+    }
+
+    /// Returns true if the current hot threads are the assigned ones.
+    pub fn stable(&self) -> bool {
+        self.stable
+    }
+
+    /// Advances the state machine with a snapshot taken at `now`.
+    ///
+    /// The returned decisions are in the order they should be acted upon, and are never empty.
+    pub fn step(&mut self, snapshot: &Snapshot, now: Instant) -> Vec<Decision> {
+        let mut decisions = Vec::new();
+        let hot = &snapshot.hot;
+        let changing_soon_fraction = self.settling_period / 10;
+        let changing_soon_period = changing_soon_fraction * 8;
+
+        if self.hot.as_ref() != Some(hot) {
+            self.hot = Some(hot.clone());
+            self.last_changed = Some(now);
+            if self.assigned.as_ref() == Some(hot) {
+                decisions.push(Decision::Returned(hot.clone()));
+                self.stable = true;
+            } else {
+                decisions.push(Decision::Changed(hot.clone()));
+                self.stable = false;
+            }
+        } else if !self.stable {
+            let elapsed = self.last_changed
+                .map(|changed| now.duration_since(changed))
+                .unwrap_or_default();
+            if !self.announced && elapsed > changing_soon_period {
+                decisions.push(Decision::AnnounceSettling {
+                    remaining: changing_soon_fraction * 2,
+                });
+                self.announced = true;
+            }
+            if elapsed > self.settling_period {
+                decisions.push(Decision::Assign(hot.clone()));
+                self.assigned = Some(hot.clone());
+                self.stable = true;
+                self.announced = false;
+                return decisions;
+            }
+        }
+        if self.stable && !snapshot.in_place {
+            decisions.push(Decision::Correct(hot.clone()));
+        }
+        if decisions.is_empty() {
+            decisions.push(Decision::Wait);
+        }
+        decisions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: &[u32] = &[1, 2, 3];
+    const B: &[u32] = &[1, 2, 4];
+
+    fn snapshot(hot: &[u32], in_place: bool) -> Snapshot {
+        Snapshot {
+            hot: hot.to_vec(),
+            in_place,
+        }
+    }
+
+    fn secs(secs: u64) -> Duration {
+        Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn assigns_after_settling() {
+        let start = Instant::now();
+        let mut governor = Governor::new(secs(15));
+        let snap = snapshot(A, false);
+        assert_eq!(governor.step(&snap, start), vec![Decision::Changed(A.to_vec())]);
+        assert_eq!(governor.step(&snap, start + secs(12)), vec![Decision::Wait]);
+        assert_eq!(
+            governor.step(&snap, start + secs(13)),
+            vec![Decision::AnnounceSettling { remaining: secs(3) }]
+        );
+        assert_eq!(governor.step(&snap, start + secs(14)), vec![Decision::Wait]);
+        assert_eq!(governor.step(&snap, start + secs(16)), vec![Decision::Assign(A.to_vec())]);
+        assert_eq!(governor.assigned(), Some(A));
+        assert!(governor.stable());
+        let snap = snapshot(A, true);
+        assert_eq!(governor.step(&snap, start + secs(17)), vec![Decision::Wait]);
+    }
+
+    #[test]
+    fn restarts_settling_on_change() {
+        let start = Instant::now();
+        let mut governor = Governor::new(secs(15));
+        governor.step(&snapshot(A, false), start);
+        let decisions = governor.step(&snapshot(B, false), start + secs(10));
+        assert_eq!(decisions, vec![Decision::Changed(B.to_vec())]);
+        let decisions = governor.step(&snapshot(B, false), start + secs(20));
+        assert_eq!(decisions, vec![Decision::Wait]);
+        let decisions = governor.step(&snapshot(B, false), start + secs(26));
+        assert_eq!(
+            decisions,
+            vec![
+                Decision::AnnounceSettling { remaining: secs(3) },
+                Decision::Assign(B.to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn accepts_assigned_threads_returning() {
+        let start = Instant::now();
+        let mut governor = Governor::new(secs(0));
+        governor.step(&snapshot(A, false), start);
+        governor.step(&snapshot(A, false), start + secs(1));
+        assert_eq!(governor.assigned(), Some(A));
+        let decisions = governor.step(&snapshot(B, false), start + secs(2));
+        assert_eq!(decisions, vec![Decision::Changed(B.to_vec())]);
+        assert!(!governor.stable());
+        let decisions = governor.step(&snapshot(A, true), start + secs(3));
+        assert_eq!(decisions, vec![Decision::Returned(A.to_vec())]);
+        assert!(governor.stable());
+    }
+
+    #[test]
+    fn corrects_displaced_threads() {
+        let start = Instant::now();
+        let mut governor = Governor::new(secs(0));
+        governor.step(&snapshot(A, false), start);
+        governor.step(&snapshot(A, false), start + secs(1));
+        let decisions = governor.step(&snapshot(A, false), start + secs(2));
+        assert_eq!(decisions, vec![Decision::Correct(A.to_vec())]);
+        let decisions = governor.step(&snapshot(B, false), start + secs(3));
+        assert_eq!(decisions, vec![Decision::Changed(B.to_vec())]);
+        let decisions = governor.step(&snapshot(A, false), start + secs(4));
+        assert_eq!(decisions, vec![Decision::Returned(A.to_vec()), Decision::Correct(A.to_vec())]);
+    }
+}
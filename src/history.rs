@@ -0,0 +1,162 @@
+//! Bounded histories of timestamped activity samples.
+
+use std::collections::VecDeque;
+use std::time::Instant;
+
+/// The activity of a thread over one poll.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Sample {
+    /// When the poll ended.
+    pub at: Instant,
+    /// The counter units used since the previous poll.
+    pub delta: u64,
+}
+
+/// A ring buffer of the most recent samples, oldest first.
+///
+/// Queries take the start of the window they cover; a sample belongs to the window if it was
+/// taken at or after `since`. Once the buffer is full the oldest samples are dropped, so a window
+/// reaching further back than the buffer only covers what is left.
+#[derive(Debug, Clone)]
+pub struct History {
+    samples: VecDeque<Sample>,
+    capacity: usize,
+}
+
+impl History {
+    /// Creates a history holding at most `capacity` samples.
+    pub fn new(capacity: usize) -> History {
+        History {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Changes the capacity, dropping the oldest samples if there are too many.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.samples.len() > capacity {
+            self.samples.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Records a sample, dropping the oldest one if the history is full.
+    pub fn push(&mut self, sample: Sample) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Returns the newest sample.
+    pub fn latest(&self) -> Option<&Sample> {
+        self.samples.back()
+    }
+
+    /// Returns every retained sample, oldest first.
+    pub fn samples(&self) -> impl Iterator<Item = &Sample> {
+        self.samples.iter()
+    }
+
+    /// Returns the samples taken at or after `since`, oldest first.
+    pub fn since(&self, since: Instant) -> impl Iterator<Item = &Sample> {
+        self.samples.iter().filter(move |sample| sample.at >= since)
+    }
+
+    /// Returns the total activity in the window.
+    pub fn total(&self, since: Instant) -> u64 {
+        self.since(since).map(|sample| sample.delta).sum()
+    }
+
+    /// Returns the mean activity per sample in the window.
+    pub fn mean(&self, since: Instant) -> Option<f64> {
+        let (count, total) = self.since(since)
+            .fold((0, 0), |(count, total), sample| (count + 1, total + sample.delta));
+        if count == 0 {
+            None
+        } else {
+            Some(total as f64 / count as f64)
+        }
+    }
+
+    /// Returns the highest activity of any sample in the window.
+    pub fn max(&self, since: Instant) -> Option<u64> {
+        self.since(since).map(|sample| sample.delta).max()
+    }
+
+    /// Returns the nearest-rank `percentile` (0 to 100) of the activity in the window.
+    pub fn percentile(&self, since: Instant, percentile: f64) -> Option<u64> {
+        let mut deltas: Vec<u64> = self.since(since).map(|sample| sample.delta).collect();
+        if deltas.is_empty() {
+            return None;
+        }
+        deltas.sort_unstable();
+        let rank = (percentile.clamp(0.0, 100.0) / 100.0 * deltas.len() as f64).ceil() as usize;
+        Some(deltas[rank.max(1) - 1])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn history(start: Instant, deltas: &[u64]) -> History {
+        let mut history = History::new(4);
+        for (i, &delta) in deltas.iter().enumerate() {
+            history.push(Sample {
+                at: start + Duration::from_secs(i as u64),
+                delta,
+            });
+        }
+        history
+    }
+
+    #[test]
+    fn keeps_the_newest_samples() {
+        let start = Instant::now();
+        let history = history(start, &[1, 2, 3, 4, 5, 6]);
+        assert_eq!(history.len(), 4);
+        let deltas: Vec<_> = history.samples().map(|sample| sample.delta).collect();
+        assert_eq!(deltas, vec![3, 4, 5, 6]);
+        assert_eq!(history.latest().unwrap().delta, 6);
+    }
+
+    #[test]
+    fn summarises_windows() {
+        let start = Instant::now();
+        let history = history(start, &[10, 40, 20, 30]);
+        assert_eq!(history.total(start), 100);
+        assert_eq!(history.mean(start), Some(25.0));
+        assert_eq!(history.max(start), Some(40));
+        let recent = start + Duration::from_secs(2);
+        assert_eq!(history.mean(recent), Some(25.0));
+        assert_eq!(history.max(recent), Some(30));
+        assert_eq!(history.mean(start + Duration::from_secs(9)), None);
+    }
+
+    #[test]
+    fn computes_nearest_rank_percentiles() {
+        let start = Instant::now();
+        let history = history(start, &[10, 40, 20, 30]);
+        assert_eq!(history.percentile(start, 0.0), Some(10));
+        assert_eq!(history.percentile(start, 50.0), Some(20));
+        assert_eq!(history.percentile(start, 75.0), Some(30));
+        assert_eq!(history.percentile(start, 100.0), Some(40));
+    }
+}
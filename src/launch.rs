@@ -0,0 +1,297 @@
+//! Running the game, or a launcher which eventually starts it, as a child process.
+
+use std::collections::HashMap;
+#[cfg(target_os = "linux")]
+use std::{fmt, mem, ptr};
+use std::io;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use backend::{Backend, OsProcess};
+use {Error, HcbResult};
+
+/// How many ancestors are looked through when deciding whether a process belongs to a family.
+const MAX_DEPTH: usize = 32;
+
+/// A command spawned as a child process.
+#[derive(Debug)]
+pub struct Launch {
+    id: u32,
+    exited: Arc<AtomicBool>,
+    waiter: JoinHandle<io::Result<ExitStatus>>,
+}
+
+impl Launch {
+    /// Spawns `command`, the program followed by its arguments.
+    pub fn spawn(command: &[String]) -> HcbResult<Launch> {
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| Error::Config("No command to run".to_owned()))?;
+        let mut command = Command::new(program);
+        command.args(args);
+        #[cfg(target_os = "linux")]
+        unsafe {
+            use std::os::unix::process::CommandExt;
+            // The child must not inherit the signals `Signals::block` held back.
+            command.pre_exec(|| {
+                let mut set = mem::zeroed();
+                ::libc::sigemptyset(&mut set);
+                ::libc::pthread_sigmask(::libc::SIG_SETMASK, &set, ptr::null_mut());
+                Ok(())
+            });
+        }
+        let mut child = command.spawn()?;
+        let id = child.id();
+        let exited = Arc::new(AtomicBool::new(false));
+        let flag = exited.clone();
+        let waiter = thread::spawn(move || {
+            let status = child.wait();
+            flag.store(true, Ordering::SeqCst);
+            status
+        });
+        Ok(Launch { id, exited, waiter })
+    }
+
+    /// Returns the child's process id.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the processes descended from the child, which notices when the child exits.
+    pub fn family(&self) -> Family {
+        Family {
+            exited: Some(self.exited.clone()),
+            ..Family::new(self.id)
+        }
+    }
+
+    /// Waits for the child to exit, returning its exit status.
+    pub fn wait(self) -> HcbResult<ExitStatus> {
+        match self.waiter.join() {
+            Ok(status) => Ok(status?),
+            Err(_) => Err(io::Error::other("waiting for the child failed").into()),
+        }
+    }
+}
+
+/// The termination signals, held back from every thread so that one thread can wait for them.
+#[cfg(target_os = "linux")]
+pub struct Signals {
+    set: ::libc::sigset_t,
+}
+
+#[cfg(target_os = "linux")]
+impl fmt::Debug for Signals {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Signals")
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Signals {
+    /// Blocks `SIGINT`, `SIGTERM` and `SIGHUP` in the calling thread and every thread it starts
+    /// from now on, so this must be called before any other thread is started. A child started
+    /// by `Launch::spawn` has nothing blocked.
+    pub fn block() -> HcbResult<Signals> {
+        unsafe {
+            let mut set = mem::zeroed();
+            ::libc::sigemptyset(&mut set);
+            for &signal in &[::libc::SIGINT, ::libc::SIGTERM, ::libc::SIGHUP] {
+                ::libc::sigaddset(&mut set, signal);
+            }
+            match ::libc::pthread_sigmask(::libc::SIG_BLOCK, &set, ptr::null_mut()) {
+                0 => Ok(Signals { set }),
+                code => Err(io::Error::from_raw_os_error(code).into()),
+            }
+        }
+    }
+
+    /// Waits for the blocked signals on a thread of their own, calling `handler` for each and
+    /// sending it on to the process `id`.
+    ///
+    /// A signal the terminal sent to its foreground process group is not sent on while the
+    /// child is still in this process's group, since the child has received it already.
+    pub fn forward<F: Fn() + Send + 'static>(self, id: u32, handler: F) -> HcbResult<()> {
+        let set = self.set;
+        thread::Builder::new()
+            .name("signals".to_owned())
+            .spawn(move || loop {
+                let mut info: ::libc::siginfo_t = unsafe { mem::zeroed() };
+                let signal = unsafe { ::libc::sigwaitinfo(&set, &mut info) };
+                if signal < 0 {
+                    continue;
+                }
+                let child = id as ::libc::pid_t;
+                let from_terminal = info.si_code == ::libc::SI_KERNEL
+                    && unsafe { ::libc::getpgid(child) == ::libc::getpgrp() };
+                if !from_terminal {
+                    debug!("Sending signal {} on to process {}.", signal, id);
+                    unsafe {
+                        ::libc::kill(child, signal);
+                    }
+                }
+                handler();
+            })?;
+        Ok(())
+    }
+}
+
+/// Returns the exit code a shell would report for the status: the child's own code, or 128 plus
+/// the number of the signal which killed it.
+pub fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// A process and its descendants.
+///
+/// Descendants are remembered once seen, so a process still counts after the intermediate
+/// processes between it and the root have exited, as long as they were seen while running.
+/// Members are remembered with their start times, since a process id may be reused once its
+/// process has exited.
+#[derive(Debug, Clone)]
+pub struct Family {
+    root: u32,
+    /// The start time of each member, if known.
+    members: HashMap<u32, Option<u64>>,
+    exited: Option<Arc<AtomicBool>>,
+}
+
+impl Family {
+    /// Creates the family of the process with the given id.
+    ///
+    /// The root's start time is not known, so its id must not be reused while the family is in
+    /// use, as with a launched child which has not been waited for.
+    pub fn new(root: u32) -> Family {
+        Family {
+            root,
+            members: [(root, None)].iter().cloned().collect(),
+            exited: None,
+        }
+    }
+
+    /// Creates the family of a running process.
+    pub fn of<P: OsProcess>(process: &P) -> HcbResult<Family> {
+        let root = process.id();
+        Ok(Family {
+            root,
+            members: [(root, Some(process.start_time()?))].iter().cloned().collect(),
+            exited: None,
+        })
+    }
+
+    /// Returns the id of the family's root process.
+    pub fn root(&self) -> u32 {
+        self.root
+    }
+
+    /// Returns true if the root is a launched child which has exited.
+    pub fn root_exited(&self) -> bool {
+        self.exited
+            .as_ref()
+            .is_some_and(|exited| exited.load(Ordering::SeqCst))
+    }
+
+    /// Returns true if the process with the given id and start time is a member.
+    fn is_member(&self, id: u32, start_time: u64) -> bool {
+        self.members
+            .get(&id)
+            .is_some_and(|known| known.is_none_or(|known| known == start_time))
+    }
+
+    /// Returns true if the process is the root or one of its descendants, remembering it and
+    /// its ancestors if so.
+    ///
+    /// Each ancestor must have started no later than the process it is the parent of, or the
+    /// parent has exited and its id been reused by an unrelated process.
+    pub fn adopt<B: Backend>(&mut self, backend: &B, process: &B::Process) -> bool {
+        let (mut start_time, mut parent) = match process.start_time() {
+            Ok(start_time) => (start_time, process.parent_id()),
+            Err(_) => return false,
+        };
+        let mut line = vec![(process.id(), Some(start_time))];
+        if self.is_member(process.id(), start_time) {
+            return true;
+        }
+        for _ in 0..MAX_DEPTH {
+            let id = match parent {
+                Ok(id) if id != 0 && !line.iter().any(|&(member, _)| member == id) => id,
+                _ => return false,
+            };
+            let ancestor = match backend.process(id) {
+                Ok(ancestor) => ancestor,
+                Err(_) => {
+                    // A member which has exited may still have started the process.
+                    let started_first = self.members
+                        .get(&id)
+                        .is_some_and(|known| known.is_none_or(|known| known <= start_time));
+                    if started_first {
+                        self.members.extend(line);
+                    }
+                    return started_first;
+                }
+            };
+            match ancestor.start_time() {
+                Ok(ancestor_start) if ancestor_start <= start_time => start_time = ancestor_start,
+                _ => return false,
+            }
+            if self.is_member(id, start_time) {
+                self.members.extend(line);
+                return true;
+            }
+            line.push((id, Some(start_time)));
+            parent = ancestor.parent_id();
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mock::MockBackend;
+
+    #[test]
+    fn adopts_descendants() {
+        let backend = MockBackend::new();
+        for &(id, parent) in &[(10, 1), (11, 10), (12, 11), (20, 1)] {
+            backend.add_process(id, "process", 0xff, vec![]);
+            backend.set_parent(id, parent);
+        }
+        let mut family = Family::new(10);
+        let processes = backend.processes().unwrap();
+        let adopted: Vec<u32> = processes
+            .iter()
+            .filter(|p| family.adopt(&backend, p))
+            .map(|p| p.id())
+            .collect();
+        assert_eq!(adopted, vec![10, 11, 12]);
+        assert!(!family.root_exited());
+    }
+
+    #[test]
+    fn rejects_processes_older_than_their_parent() {
+        let backend = MockBackend::new();
+        for &(id, parent, start_time) in &[(10, 1, 50), (11, 10, 60), (12, 10, 40), (13, 12, 45)] {
+            backend.add_process(id, "process", 0xff, vec![]);
+            backend.set_parent(id, parent);
+            backend.set_start_time(id, start_time);
+        }
+        let mut family = Family::of(&backend.process(10).unwrap()).unwrap();
+        let processes = backend.processes().unwrap();
+        let adopted: Vec<u32> = processes
+            .iter()
+            .filter(|p| family.adopt(&backend, p))
+            .map(|p| p.id())
+            .collect();
+        assert_eq!(adopted, vec![10, 11]);
+    }
+}
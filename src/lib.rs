@@ -1,142 +1,1513 @@
+extern crate dirs;
 #[macro_use]
 extern crate failure;
+extern crate glob;
+#[cfg(target_os = "linux")]
+extern crate libc;
+#[cfg(windows)]
 extern crate winapi;
 #[macro_use]
 extern crate log;
+extern crate regex;
+extern crate toml;
 
-use std::thread;
+use std::mem;
 use std::time::{Duration, Instant};
 
+pub use allocator::CoreAllocator;
+pub use backend::{Backend, OsProcess, OsThread};
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use config::Config;
+pub use dryrun::DryRun;
 pub use errors::{Error, HcbResult};
-pub use procext::{MonitoredProcess, MonitoredThread};
-use win::Process;
+pub use evict::Evictor;
+pub use governor::{Decision, Governor, Snapshot};
+pub use history::{History, Sample};
+pub use launch::{Family, Launch};
+pub use manager::Manager;
+pub use placement::{Mode, Placement};
+pub use policy::{AssignmentPolicy, LoadMeter, Policy, PolicyContext};
+pub use procext::{MonitoredProcess, MonitoredThread, Smoothing};
+pub use reload::{FileWatcher, ModifiedWatcher};
+pub use reserve::Reservation;
+pub use rules::{Rule, RuleEngine};
+pub use selector::Selector;
+pub use settings::Settings;
+pub use shutdown::Shutdown;
+pub use topology::Topology;
+pub use watch::{PollingWatcher, ProcessEvent, ProcessWatcher};
+#[cfg(target_os = "linux")]
+pub use linux::LinuxBackend as NativeBackend;
+#[cfg(windows)]
+pub use win::WinBackend as NativeBackend;
 
+pub mod allocator;
+pub mod backend;
+pub mod clock;
+pub mod config;
+pub mod dryrun;
 pub mod errors;
+pub mod evict;
+pub mod governor;
+pub mod history;
+pub mod launch;
+#[cfg(target_os = "linux")]
+pub mod linux;
+pub mod manager;
+#[cfg(test)]
+mod mock;
+pub mod placement;
+pub mod policy;
 pub mod procext;
+pub mod reload;
+pub mod reserve;
+pub mod rules;
+pub mod selector;
+pub mod settings;
+pub mod shutdown;
+pub mod topology;
+pub mod watch;
+#[cfg(windows)]
 pub mod win;
 
-/// Returns a handle to the Rocket League process.
-pub fn rl_process() -> HcbResult<Process> {
-    Process::all()?
-        .find(|p| {
-            p.name()
-                .map(|name| name == "RocketLeague.exe")
-                .unwrap_or(false)
-        })
-        .ok_or(Error::NoProcess)
-}
-
-fn wait_for_three_threads(
-    process: &mut MonitoredProcess,
-    poll_interval: Duration,
-) -> HcbResult<[u32; 3]> {
-    loop {
-        if let Some(active_threads) = process.thread_ids_by_activity().get(0..3) {
-            return Ok([active_threads[0], active_threads[1], active_threads[2]]);
-        }
-        thread::sleep(poll_interval);
-        process.update()?;
-    }
-}
-
-fn top_three_ideal(ids: &[u32; 3], process: &mut MonitoredProcess) -> HcbResult<[u32; 3]> {
-    let mut get_ideal = |i| {
-        process
-            .threads_mut()
-            .get_mut(&ids[i])
-            .unwrap()
-            .thread()
-            .ideal_processor()
+/// Returns a handle to the first process the selector matches.
+pub fn find_process<B: Backend>(backend: &B, selector: &Selector) -> HcbResult<B::Process> {
+    backend
+        .processes()?
+        .into_iter()
+        .find(|p| selector.matches(p))
+        .ok_or_else(|| Error::NoProcess(selector.to_string()))
+}
+
+/// Monitors the target process, assigning its most active threads to separate cores.
+pub fn manage_target<B: Backend, C: Clock>(
+    backend: &B,
+    clock: &C,
+    settings: &Settings,
+    shutdown: &Shutdown,
+) -> HcbResult<()> {
+    let process = find_process(backend, &settings.target)
+        .and_then(|process| MonitoredProcess::new(process, clock.now()))?;
+    info!("Process {} matching '{}' found.", process.process().id(), settings.target);
+    manage_threads(backend, process, clock, settings, shutdown)
+}
+
+/// Assigns the most active threads of a process to separate cores until it exits or a shutdown
+/// is requested.
+///
+/// Whichever way it stops, every thread and process it changed is given its original placement
+/// back. A requested shutdown returns `Ok`.
+pub fn manage_threads<B: Backend, C: Clock>(
+    backend: &B,
+    process: MonitoredProcess<B::Process>,
+    clock: &C,
+    settings: &Settings,
+    shutdown: &Shutdown,
+) -> HcbResult<()> {
+    let mut allocator = CoreAllocator::new();
+    let mut manager = Manager::new(backend, process, settings)?;
+    let mut watcher = PollingWatcher::new(backend, clock)?;
+    watcher.set_needed(manager.watches_processes());
+    let result = loop {
+        if shutdown.requested() {
+            info!("Shutting down.");
+            break Ok(());
+        }
+        if let Err(e) = manager.step(backend, clock.now(), &mut allocator) {
+            break Err(e);
+        }
+        let observed = watcher
+            .wait(settings.poll_interval)
+            .and_then(|events| manager.observe(backend, &events));
+        if let Err(e) = observed {
+            break Err(e);
+        }
     };
-    Ok([get_ideal(0)?, get_ideal(1)?, get_ideal(2)?])
-}
-
-fn set_top_three_ideal(ids: &[u32; 3], process: &mut MonitoredProcess) -> HcbResult<()> {
-    println!("{:?}", ids);
-    let mut core_num = 0;
-    for core in (0u32..3) {
-        core_num = ((core + 1) * 2) - 1;
-        let s: String = core_num.to_string();
-        println!("{}", s);
-        process
-            .threads_mut()
-            .get_mut(&ids[core as usize])
-            .unwrap()
-            .thread_mut()
-            .set_ideal_processor(core_num)?;
+    manager.restore(&mut allocator);
+    result
+}
+
+/// Returns true if the error means the process being looked at has exited.
+fn process_gone(err: &Error) -> bool {
+    matches!(*err, Error::ProcessExited(_))
+}
+
+/// Returns true if the manager's process has exited, whether the error says so or the process
+/// exited while the manager was reading it.
+fn manager_gone<B: Backend>(manager: &Manager<B>, err: &Error) -> bool {
+    process_gone(err) || !manager.process().process().running()
+}
+
+/// Starts managers for the waiting profiles whose targets are among `candidates`.
+fn start_managers<B: Backend>(
+    backend: &B,
+    profiles: &[Settings],
+    managers: &mut [Option<Manager<B>>],
+    mut candidates: Vec<B::Process>,
+    now: Instant,
+) {
+    let mut managed: Vec<u32> = managers
+        .iter()
+        .filter_map(|manager| manager.as_ref())
+        .map(|manager| manager.process().process().id())
+        .collect();
+    for (profile, slot) in profiles.iter().zip(managers.iter_mut()) {
+        if slot.is_some() {
+            continue;
+        }
+        let found = candidates
+            .iter()
+            .position(|p| !managed.contains(&p.id()) && profile.target.matches(p));
+        let process = match found {
+            Some(index) => candidates.swap_remove(index),
+            None => continue,
+        };
+        let id = process.id();
+        let process = match MonitoredProcess::new(process, now) {
+            Ok(process) => process,
+            Err(ref e) if process_gone(e) => continue,
+            Err(e) => {
+                warn!("Cannot monitor process {} for profile '{}': {}", id, profile.name, e);
+                continue;
+            }
+        };
+        info!(
+            "Process {} matching '{}' found for profile '{}'.",
+            id, profile.target, profile.name
+        );
+        match Manager::new(backend, process, profile) {
+            Ok(manager) => *slot = Some(manager),
+            Err(ref e) if process_gone(e) => continue,
+            Err(e) => {
+                warn!("Cannot manage process {} for profile '{}': {}", id, profile.name, e);
+                continue;
+            }
+        }
+        managed.push(id);
+    }
+}
+
+/// Keeps every profile's system-wide reservation from moving the processes any profile
+/// manages, including their descendants, so one profile never changes or restores the
+/// placement of another's threads.
+fn exempt_managed<B: Backend>(managers: &mut [Option<Manager<B>>]) {
+    let managed: Vec<u32> = managers
+        .iter()
+        .filter_map(|manager| manager.as_ref())
+        .flat_map(|manager| manager.process().process_ids())
+        .collect();
+    for manager in managers.iter_mut().filter_map(|manager| manager.as_mut()) {
+        manager.exempt(&managed);
+    }
+}
+
+/// Fails if more than one profile has the same name.
+fn check_names(profiles: &[Settings]) -> HcbResult<()> {
+    for (index, profile) in profiles.iter().enumerate() {
+        if profiles[..index].iter().any(|other| other.name == profile.name) {
+            return Err(Error::Config(format!(
+                "More than one profile is named '{}'",
+                profile.name
+            )));
+        }
     }
     Ok(())
 }
 
-/// Monitors the Rocket League process, assigning its three most active threads to separate cores.
-pub fn manage_rl_threads(poll_interval: Duration, settling_period: Duration) -> HcbResult<()> {
-    let mut process = rl_process().and_then(|p| MonitoredProcess::new(p))?;
-    info!("Process found.");
-
-    // The threads which have had affinity assigned.
-    let mut set_top_three: Option<[u32; 3]> = None;
-    // The top three threads at the moment of the last poll.
-    let mut prev_top_three = wait_for_three_threads(&mut process, poll_interval)?;
-
-    // When the thread order last changed.
-    let mut last_changed = Instant::now();
-    let changing_soon_fraction = settling_period / 10;
-    let changing_soon_period = changing_soon_fraction * 8;
-    let mut notified_changing_soon = false;
-    // Whether the current top three threads equal the ones with set affinities.
-    let mut stable = false;
-
-    loop {
-        process.update()?;
-        let mut current_top_three = wait_for_three_threads(&mut process, poll_interval)?;
-        current_top_three.sort_unstable();
-        if prev_top_three != current_top_three {
-            prev_top_three = current_top_three;
-            last_changed = Instant::now();
-            match set_top_three {
-                Some(set) if set == prev_top_three => {
-                    debug!(
-                        "Previously set top three threads returned: {:?}",
-                        prev_top_three
+/// Returns the CPUs a profile lists for its hot threads and its rules.
+fn listed_cores(profile: &Settings) -> usize {
+    profile.policy.listed(profile.hot_threads).unwrap_or(0) | RuleEngine::listed(&profile.rules)
+}
+
+/// Fails if two profiles list some of the same CPUs for their hot threads or rules, since only
+/// one of them could ever claim those CPUs while both targets run.
+fn check_cores(profiles: &[Settings]) -> HcbResult<()> {
+    for (index, profile) in profiles.iter().enumerate() {
+        let listed = listed_cores(profile);
+        if listed == 0 {
+            continue;
+        }
+        for other in &profiles[..index] {
+            let shared = listed_cores(other) & listed;
+            if shared != 0 {
+                return Err(Error::Config(format!(
+                    "Profiles '{}' and '{}' both assign CPUs {}",
+                    other.name,
+                    profile.name,
+                    rules::cpu_list(shared)
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the shortest poll interval of any profile.
+fn shortest_poll_interval(profiles: &[Settings]) -> Duration {
+    profiles
+        .iter()
+        .map(|profile| profile.poll_interval)
+        .min()
+        .unwrap_or_else(|| Duration::from_secs(1))
+}
+
+/// Fails if any changed profile could never be satisfied, whether for the process it already
+/// manages or on this machine, or if a managed process's hot threads could not be placed again
+/// beside the CPUs the other profiles hold.
+fn check_profiles<B: Backend>(
+    backend: &B,
+    profiles: &[Settings],
+    managers: &[Option<Manager<B>>],
+    allocator: &CoreAllocator,
+    changed: &[Settings],
+) -> HcbResult<()> {
+    check_names(changed)?;
+    check_cores(changed)?;
+    // The profiles whose managers stop release their CPUs before the others switch.
+    let mut allocator = allocator.clone();
+    for profile in profiles {
+        let kept = changed
+            .iter()
+            .any(|new| new.name == profile.name && new.target == profile.target);
+        if !kept {
+            allocator.release(&profile.name);
+        }
+    }
+    for new in changed {
+        let current = profiles
+            .iter()
+            .zip(managers)
+            .find(|&(profile, _)| profile.name == new.name);
+        let result = match current {
+            Some((profile, _)) if profile == new => continue,
+            Some((profile, Some(manager))) if profile.target == new.target => {
+                match manager.check(backend, new, &allocator) {
+                    Err(ref e) if manager_gone(manager, e) => Ok(()),
+                    result => result,
+                }
+            }
+            _ => manager::check_waiting(backend, new),
+        };
+        if let Err(e) = result {
+            // Config displays its own full stop after the message.
+            let reason = e.to_string();
+            return Err(Error::Config(format!(
+                "Profile '{}': {}",
+                new.name,
+                reason.trim_end_matches('.')
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the profiles with `changed`, which are matched to the current profiles by name.
+///
+/// The managers of removed profiles, and of profiles whose target changed, restore their
+/// processes' placement first, so the CPUs they held are free for the other profiles. The
+/// remaining managers switch to their changed settings in place, and a manager which cannot
+/// switch keeps its current settings.
+fn apply_profiles<B: Backend>(
+    backend: &B,
+    profiles: &mut Vec<Settings>,
+    managers: &mut Vec<Option<Manager<B>>>,
+    allocator: &mut CoreAllocator,
+    changed: Vec<Settings>,
+) {
+    let mut old: Vec<(Settings, Option<Manager<B>>)> =
+        profiles.drain(..).zip(managers.drain(..)).collect();
+    for (profile, slot) in &mut old {
+        let keep = changed
+            .iter()
+            .any(|new| new.name == profile.name && new.target == profile.target);
+        if !keep {
+            if let Some(mut manager) = slot.take() {
+                manager.restore(allocator);
+            }
+        }
+    }
+    for new in changed {
+        let slot = match old.iter().position(|(profile, _)| profile.name == new.name) {
+            Some(index) => {
+                let (profile, mut slot) = old.swap_remove(index);
+                if new.target != profile.target {
+                    info!(
+                        "Profile '{}' now waits for a process matching '{}'.",
+                        new.name, new.target
                     );
-                    stable = true;
                 }
-                Some(_) | None => {
-                    debug!("Top three threads changed: {:?}", prev_top_three);
-                    stable = false;
+                let result = match slot {
+                    Some(ref mut manager) if new != profile => {
+                        manager.reconfigure(backend, &new, allocator).map_err(|e| {
+                            let gone = manager_gone(manager, &e);
+                            (e, gone)
+                        })
+                    }
+                    _ => Ok(()),
+                };
+                match result {
+                    Ok(()) => slot,
+                    Err((_, true)) => {
+                        if let Some(mut manager) = slot {
+                            manager.restore(allocator);
+                        }
+                        None
+                    }
+                    Err((e, false)) => {
+                        warn!("Keeping the current settings of profile '{}': {}", new.name, e);
+                        profiles.push(profile);
+                        managers.push(slot);
+                        continue;
+                    }
                 }
             }
-        } else {
-            if !stable && !notified_changing_soon && last_changed.elapsed() > changing_soon_period {
+            None => {
                 info!(
-                    "Threads appear to have settled. Assigning affinities on the next poll if stable after {} seconds.",
-                    (changing_soon_fraction * 2).as_secs()
+                    "Profile '{}' added. Waiting for a process matching '{}'.",
+                    new.name, new.target
                 );
-                notified_changing_soon = true;
+                None
+            }
+        };
+        profiles.push(new);
+        managers.push(slot);
+    }
+    for (profile, _) in old {
+        info!("Profile '{}' removed.", profile.name);
+    }
+}
+
+/// Watches for the targets of every profile and manages each one found, until a shutdown is
+/// requested.
+///
+/// Each profile manages at most one process at a time, and a process is only managed by the
+/// first profile to find it. Profiles share the CPUs through a `CoreAllocator`, so their hot
+/// threads never claim the same CPU. When a target exits its placement is restored and its
+/// profile waits for it to return. Every profile polls at the shortest poll interval of any.
+///
+/// Running processes are enumerated at startup and whenever a target exits. Otherwise only the
+/// processes `watcher` reports starting are considered, as soon as it reports them, and the
+/// watcher is told no events are needed while every profile is managing its target.
+pub fn manage_targets<B: Backend, C: Clock, W: ProcessWatcher>(
+    backend: &B,
+    clock: &C,
+    watcher: &mut W,
+    profiles: &[Settings],
+    shutdown: &Shutdown,
+) -> HcbResult<()> {
+    manage_reloading(backend, clock, watcher, profiles, shutdown, None, || None)
+}
+
+/// Manages the targets of every profile like `manage_targets`, but only among `family`.
+///
+/// Returns once a managed target exits, or once the family's root has exited while no target is
+/// managed.
+pub fn manage_family<B: Backend, C: Clock, W: ProcessWatcher>(
+    backend: &B,
+    clock: &C,
+    watcher: &mut W,
+    profiles: &[Settings],
+    shutdown: &Shutdown,
+    family: Family,
+) -> HcbResult<()> {
+    manage_reloading(backend, clock, watcher, profiles, shutdown, Some(family), || None)
+}
+
+/// Manages the targets of every profile like `manage_targets`, or like `manage_family` when
+/// given a family, while taking changes to the profiles from `reload`.
+///
+/// `reload` is called once per poll and returns the whole new set of profiles when they have
+/// changed. Profiles are matched to the current ones by name. Changed timing applies from the
+/// next poll, while a changed placement restores and reassigns the managed threads without
+/// waiting for them to settle again. Profiles which could never be satisfied, or which share a
+/// name, are rejected with a warning and the current profiles are kept.
+pub fn manage_reloading<B, C, W, R>(
+    backend: &B,
+    clock: &C,
+    watcher: &mut W,
+    profiles: &[Settings],
+    shutdown: &Shutdown,
+    mut family: Option<Family>,
+    mut reload: R,
+) -> HcbResult<()>
+where
+    B: Backend,
+    C: Clock,
+    W: ProcessWatcher,
+    R: FnMut() -> Option<Vec<Settings>>,
+{
+    check_names(profiles)?;
+    check_cores(profiles)?;
+    let mut profiles = profiles.to_vec();
+    let mut poll_interval = shortest_poll_interval(&profiles);
+    let mut allocator = CoreAllocator::new();
+    let mut managers: Vec<Option<Manager<B>>> = profiles.iter().map(|_| None).collect();
+    for profile in &profiles {
+        info!("Waiting for a process matching '{}'.", profile.target);
+    }
+
+    let mut rescan = true;
+    let mut started = Vec::new();
+    let mut next_step = clock.now();
+    let result = (|| loop {
+        if shutdown.requested() {
+            info!("Shutting down.");
+            return Ok(());
+        }
+        if let Some(ref family) = family {
+            if family.root_exited() && managers.iter().all(Option::is_none) {
+                info!("Process {} exited.", family.root());
+                return Ok(());
+            }
+        }
+        if clock.now() >= next_step {
+            if let Some(changed) = reload() {
+                match check_profiles(backend, &profiles, &managers, &allocator, &changed) {
+                    Ok(()) => {
+                        apply_profiles(
+                            backend,
+                            &mut profiles,
+                            &mut managers,
+                            &mut allocator,
+                            changed,
+                        );
+                        poll_interval = shortest_poll_interval(&profiles);
+                        rescan = true;
+                    }
+                    Err(e) => warn!("Keeping the current profiles: {}", e),
+                }
             }
-            if !stable && last_changed.elapsed() > settling_period {
-                info!("Assigning thread affinities.");
-                set_top_three_ideal(&prev_top_three, &mut process)?;
-                set_top_three = Some(prev_top_three);
-                stable = true;
-                notified_changing_soon = false;
+        }
+        if managers.iter().any(Option::is_none) {
+            let mut candidates = if rescan {
+                backend.processes()?
+            } else {
+                mem::take(&mut started)
+            };
+            if let Some(ref mut family) = family {
+                candidates.retain(|p| family.adopt(backend, p));
             }
+            start_managers(backend, &profiles, &mut managers, candidates, clock.now());
         }
-        if stable && &top_three_ideal(&current_top_three, &mut process)? != &[1, 3, 5] {
-            info!("Correcting affinities.");
-            set_top_three_ideal(&prev_top_three, &mut process)?;
+        rescan = false;
+        started.clear();
+        if clock.now() >= next_step {
+            exempt_managed(&mut managers);
+            let mut exited = false;
+            for slot in &mut managers {
+                let stopped = match *slot {
+                    Some(ref mut manager) => {
+                        match manager.step(backend, clock.now(), &mut allocator) {
+                            Ok(()) => false,
+                            Err(ref e) if manager_gone(manager, e) => {
+                                let settings = manager.settings();
+                                if family.is_some() {
+                                    info!("Process for profile '{}' exited.", settings.name);
+                                } else {
+                                    info!(
+                                        "Process for profile '{}' exited. Waiting for a process matching '{}'.",
+                                        settings.name, settings.target
+                                    );
+                                }
+                                manager.restore(&mut allocator);
+                                exited = true;
+                                true
+                            }
+                            Err(e) => {
+                                // Only this profile stops, and it starts over with whichever
+                                // process matches it next.
+                                let settings = manager.settings();
+                                warn!(
+                                    "Restoring the process of profile '{}' and waiting for a process matching '{}' again: {}",
+                                    settings.name, settings.target, e
+                                );
+                                manager.restore(&mut allocator);
+                                true
+                            }
+                        }
+                    }
+                    None => false,
+                };
+                if stopped {
+                    *slot = None;
+                    // A process which was already running may match the profile now.
+                    rescan = true;
+                }
+            }
+            if exited && family.is_some() {
+                // The launched game has exited, so there is nothing left to wait for.
+                return Ok(());
+            }
+            next_step = clock.now() + poll_interval;
+        }
+        let timeout = next_step.saturating_duration_since(clock.now());
+        watcher.set_needed(
+            family.is_some()
+                || managers
+                    .iter()
+                    .any(|slot| slot.as_ref().is_none_or(Manager::watches_processes)),
+        );
+        let events = watcher.wait(timeout)?;
+        for manager in managers.iter_mut().filter_map(|manager| manager.as_mut()) {
+            manager.observe(backend, &events)?;
+        }
+        // Undoes any moves of processes another profile has just adopted as descendants.
+        exempt_managed(&mut managers);
+        for event in events {
+            match event {
+                ProcessEvent::Started(id) => {
+                    if family.is_none() && managers.iter().all(Option::is_some) {
+                        continue;
+                    }
+                    // Family members are adopted as they start, in case their parents exit
+                    // before a profile looks for its target.
+                    let process = match backend.process(id) {
+                        Ok(process) => process,
+                        Err(_) => continue,
+                    };
+                    if let Some(ref mut family) = family {
+                        family.adopt(backend, &process);
+                    }
+                    started.push(process);
+                }
+                ProcessEvent::Exited(_) => {}
+                ProcessEvent::Overflow => rescan = true,
+            }
         }
-        thread::sleep(poll_interval)
+    })();
+
+    for manager in managers.iter_mut().filter_map(|manager| manager.as_mut()) {
+        manager.restore(&mut allocator);
     }
+    result
 }
 
-//#[cfg(test)]
-//mod tests {
-//    use super::*;
-//
-//    #[test]
-//    fn finds_rl() {
-//        let process = rl_process().unwrap();
-//        println!("{:?}", process)
-//    }
-//}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::{self, Log, Metadata, Record};
+    use mock::{Event, Frame, MockBackend, MockClock};
+    use std::cell::{Cell, RefCell};
+    use std::iter;
+    use std::sync::Once;
+
+    thread_local!(static MESSAGES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) });
+
+    /// Records log messages per test thread so tests can check what was announced.
+    struct CaptureLogger;
+
+    impl Log for CaptureLogger {
+        fn enabled(&self, _: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            MESSAGES.with(|m| m.borrow_mut().push(record.args().to_string()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CaptureLogger = CaptureLogger;
+
+    fn capture_logs() {
+        static INIT: Once = Once::new();
+        INIT.call_once(|| {
+            log::set_logger(&LOGGER).unwrap();
+            log::set_max_level(log::LevelFilter::Trace);
+        });
+        MESSAGES.with(|m| m.borrow_mut().clear());
+    }
+
+    fn logged(text: &str) -> bool {
+        MESSAGES.with(|m| m.borrow().iter().any(|message| message.contains(text)))
+    }
+
+    /// Threads 1, 2 and 3 are the busiest, with two idle threads alongside.
+    fn busy() -> Frame {
+        Frame::from_activity(&[(1, 100), (2, 90), (3, 80), (4, 1), (5, 1)])
+    }
+
+    /// Thread 4 briefly overtakes thread 3.
+    fn spike() -> Frame {
+        Frame::from_activity(&[(1, 100), (2, 90), (3, 1), (4, 80), (5, 1)])
+    }
+
+    /// Builds a script from `(frame, repetitions)` pairs, after an initial idle frame.
+    fn script(parts: &[(Frame, usize)]) -> Vec<Frame> {
+        let mut script = vec![Frame::default()];
+        for &(ref frame, count) in parts {
+            script.extend(iter::repeat_n(frame.clone(), count));
+        }
+        script
+    }
+
+    /// The default settings with soft placement, which the mock backend supports on every
+    /// platform.
+    fn soft() -> Settings {
+        Settings {
+            mode: Mode::Soft,
+            ..Settings::default()
+        }
+    }
+
+    /// Runs the manager over a script with a one second poll and a fifteen second settle.
+    fn run(script: Vec<Frame>) -> MockBackend {
+        run_with(script, &soft())
+    }
+
+    fn run_with(script: Vec<Frame>, settings: &Settings) -> MockBackend {
+        capture_logs();
+        let backend = MockBackend::new();
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script);
+        let result = manage_target(&backend, &MockClock::new(&backend), settings, &Shutdown::new());
+        match result {
+            Err(Error::ProcessExited(1000)) => {}
+            other => panic!("expected the process to exit, got {:?}", other),
+        }
+        backend
+    }
+
+    fn assignment(ids: &[u32]) -> Vec<Event> {
+        ids.iter()
+            .zip(&[1, 3, 5, 7])
+            .map(|(&thread, &processor)| Event::SetIdealProcessor { thread, processor })
+            .collect()
+    }
+
+    /// The events which put threads back on their original ideal processor and affinity mask.
+    fn restoration(ids: &[u32]) -> Vec<Event> {
+        ids.iter()
+            .flat_map(|&thread| {
+                vec![
+                    Event::SetIdealProcessor {
+                        thread,
+                        processor: 0,
+                    },
+                    Event::SetAffinityMask { thread, mask: 0xff },
+                ]
+            })
+            .collect()
+    }
+
+    /// A clock which requests a shutdown once it has slept a given number of times.
+    struct StopAfter<C = MockClock> {
+        clock: C,
+        sleeps: Cell<u32>,
+        shutdown: Shutdown,
+    }
+
+    impl<C: Clock> Clock for StopAfter<C> {
+        fn now(&self) -> Instant {
+            self.clock.now()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.clock.sleep(duration);
+            self.sleeps.set(self.sleeps.get().saturating_sub(1));
+            if self.sleeps.get() == 0 {
+                self.shutdown.request();
+            }
+        }
+    }
+
+    /// A manual clock which starts a process once it has slept a given number of times.
+    struct SpawnAfter<'a> {
+        clock: MockClock,
+        sleeps: Cell<u32>,
+        spawn: Box<dyn Fn() + 'a>,
+    }
+
+    impl<'a> Clock for SpawnAfter<'a> {
+        fn now(&self) -> Instant {
+            self.clock.now()
+        }
+
+        fn sleep(&self, duration: Duration) {
+            self.clock.sleep(duration);
+            if self.sleeps.get() == 1 {
+                (self.spawn)();
+            }
+            self.sleeps.set(self.sleeps.get().saturating_sub(1));
+        }
+    }
+
+    #[test]
+    fn finds_rl() {
+        let backend = MockBackend::new();
+        backend.add_process(7, "explorer.exe", 0xff, vec![Frame::default()]);
+        backend.add_process(1000, "RocketLeague.exe", 0xff, vec![Frame::default()]);
+        assert_eq!(find_process(&backend, &Selector::default()).unwrap().id(), 1000);
+    }
+
+    #[test]
+    fn reports_the_selector_which_found_nothing() {
+        let backend = MockBackend::new();
+        backend.add_process(7, "explorer.exe", 0xff, vec![Frame::default()]);
+        let selector = "glob:Rocket*".parse().unwrap();
+        let err = find_process(&backend, &selector).unwrap_err();
+        assert_eq!(err.to_string(), "No process matching 'name:glob:Rocket*' found.");
+    }
+
+    #[test]
+    fn waits_for_threads_to_settle() {
+        let backend = run(script(&[(busy(), 13)]));
+        assert_eq!(backend.events(), vec![]);
+        assert!(!logged("Threads appear to have settled"));
+    }
+
+    #[test]
+    fn announces_settling_before_assigning() {
+        let backend = run(script(&[(busy(), 16)]));
+        assert!(logged("Threads appear to have settled"));
+        assert!(!logged("Assigning thread affinities."));
+        assert_eq!(backend.events(), vec![]);
+    }
+
+    #[test]
+    fn assigns_settled_threads() {
+        let backend = run(script(&[(busy(), 17)]));
+        assert!(logged("Assigning thread affinities."));
+        assert_eq!(backend.events(), assignment(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn restarts_settling_when_threads_change() {
+        let backend = run(script(&[(busy(), 10), (spike(), 1), (busy(), 10)]));
+        assert!(logged("Hot threads changed: [1, 2, 4]"));
+        assert_eq!(backend.events(), vec![]);
+    }
+
+    #[test]
+    fn smoothing_rides_out_spikes() {
+        let settings = Settings {
+            smoothing: Smoothing::new(0.3, 0.2).unwrap(),
+            ..soft()
+        };
+        let backend = run_with(script(&[(busy(), 10), (spike(), 1), (busy(), 7)]), &settings);
+        assert!(!logged("Hot threads changed: [1, 2, 4]"));
+        assert_eq!(backend.events(), assignment(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn accepts_previously_set_threads_returning() {
+        let backend = run(script(&[(busy(), 17), (spike(), 1), (busy(), 3)]));
+        assert!(logged("Hot threads changed: [1, 2, 4]"));
+        assert!(logged("Previously set hot threads returned: [1, 2, 3]"));
+        assert_eq!(backend.events(), assignment(&[1, 2, 3]));
+    }
+
+    #[test]
+    fn reassigns_changed_threads() {
+        let backend = run(script(&[(busy(), 17), (spike(), 17)]));
+        let mut expected = assignment(&[1, 2, 3]);
+        expected.extend(restoration(&[3]));
+        expected.extend(assignment(&[1, 2, 4]));
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn restores_placement_on_shutdown() {
+        capture_logs();
+        let backend = MockBackend::new();
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script(&[(busy(), 30)]));
+        let clock = StopAfter {
+            clock: MockClock::new(&backend),
+            sleeps: Cell::new(18),
+            shutdown: Shutdown::new(),
+        };
+        let result = manage_target(&backend, &clock, &soft(), &clock.shutdown);
+        assert!(result.is_ok());
+        assert!(logged("Restoring original thread placement."));
+        let mut expected = assignment(&[1, 2, 3]);
+        expected.extend(restoration(&[1, 2, 3]));
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn corrects_moved_threads() {
+        let backend = run(script(&[(busy(), 17), (busy().perturb(2, 0), 1), (busy(), 1)]));
+        assert!(logged("Correcting affinities."));
+        let mut expected = assignment(&[1, 2, 3]);
+        expected.extend(assignment(&[1, 2, 3]));
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn manages_configured_number_of_threads() {
+        let settings = Settings {
+            hot_threads: 2,
+            ..soft()
+        };
+        let backend = run_with(script(&[(busy(), 17)]), &settings);
+        assert_eq!(backend.events(), assignment(&[1, 2]));
+    }
+
+    #[test]
+    fn rejects_more_threads_than_cpus() {
+        let backend = MockBackend::new();
+        backend.add_process(1000, "RocketLeague.exe", 0b1111, script(&[(busy(), 1)]));
+        let settings = Settings {
+            hot_threads: 5,
+            ..soft()
+        };
+        match manage_target(&backend, &MockClock::new(&backend), &settings, &Shutdown::new()) {
+            Err(Error::HotThreads {
+                requested: 5,
+                available: 4,
+            }) => {}
+            other => panic!("expected the thread count to be rejected, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn assigns_cores_chosen_by_policy() {
+        let settings = Settings {
+            policy: "6,2,4".parse().unwrap(),
+            ..soft()
+        };
+        let backend = run_with(script(&[(busy(), 17)]), &settings);
+        let expected: Vec<_> = [(1, 6), (2, 2), (3, 4)]
+            .iter()
+            .map(|&(thread, processor)| Event::SetIdealProcessor { thread, processor })
+            .collect();
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn pins_threads_in_hard_mode() {
+        let settings = Settings {
+            mode: Mode::Hard,
+            ..soft()
+        };
+        let script = script(&[
+            (busy(), 17),
+            (busy().perturb(2, 0), 1),
+            (busy().perturb_affinity(2, 0xff), 1),
+        ]);
+        let backend = run_with(script, &settings);
+        let pinning: Vec<_> = [(1, 0b10), (2, 0b1000), (3, 0b10_0000)]
+            .iter()
+            .map(|&(thread, mask)| Event::SetAffinityMask { thread, mask })
+            .collect();
+        let mut expected = pinning.clone();
+        expected.extend(pinning);
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn evicts_other_threads_from_hot_cores() {
+        let settings = Settings {
+            evict: true,
+            ..soft()
+        };
+        let late = Frame::from_activity(&[(1, 100), (2, 90), (3, 80), (4, 1), (5, 1), (6, 1)]);
+        let backend = run_with(script(&[(busy(), 17), (late, 1)]), &settings);
+        let mut expected = assignment(&[1, 2, 3]);
+        expected.extend(
+            [4, 5, 6]
+                .iter()
+                .map(|&thread| Event::SetAffinityMask { thread, mask: 0b1101_0101 }),
+        );
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn reserves_hot_cores_system_wide_until_exit() {
+        capture_logs();
+        let settings = Settings {
+            reserve_system: true,
+            ..soft()
+        };
+        let backend = MockBackend::new();
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script(&[(busy(), 18)]));
+        let discord = Frame::from_activity(&[(21, 0), (22, 0)]);
+        let mut frames = vec![discord.clone().perturb_affinity(22, 0b1100)];
+        frames.extend(iter::repeat_n(discord, 20));
+        backend.add_process(2000, "Discord.exe", 0xff, frames);
+        backend.add_process(3000, "obs64.exe", 0b1, vec![Frame::from_activity(&[(31, 0)]); 20]);
+        let clock = SpawnAfter {
+            clock: MockClock::new(&backend),
+            sleeps: Cell::new(18),
+            spawn: Box::new(|| {
+                let frames = vec![Frame::from_activity(&[(41, 0)]); 2];
+                backend.add_process(4000, "steam.exe", 0xff, frames);
+            }),
+        };
+        let result = manage_target(&backend, &clock, &settings, &Shutdown::new());
+        assert!(result.is_err());
+        let mut expected = assignment(&[1, 2, 3]);
+        // Each thread keeps the CPUs it had apart from the reserved ones, and gets them back.
+        expected.extend(
+            [(21, 0b1101_0101), (22, 0b0100), (41, 0b1101_0101)]
+                .iter()
+                .chain(&[(21, 0xff), (22, 0b1100), (41, 0xff)])
+                .map(|&(thread, mask)| Event::SetAffinityMask { thread, mask }),
+        );
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn reserves_only_the_processes_of_the_targets_owner() {
+        let settings = Settings {
+            reserve_system: true,
+            ..soft()
+        };
+        let backend = MockBackend::new();
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script(&[(busy(), 18)]));
+        let idle = |thread| vec![Frame::from_activity(&[(thread, 0)]); 20];
+        backend.add_process(2000, "Discord.exe", 0xff, idle(21));
+        backend.add_process(3000, "sshd", 0xff, idle(31));
+        backend.set_owner(3000, Some(0));
+        backend.add_process(4000, "kworker/0:1", 0xff, idle(41));
+        backend.set_owner(4000, None);
+        let clock = MockClock::new(&backend);
+        let result = manage_target(&backend, &clock, &settings, &Shutdown::new());
+        assert!(result.is_err());
+        let mut expected = assignment(&[1, 2, 3]);
+        expected.extend(
+            [(21, 0b1101_0101), (21, 0xff)]
+                .iter()
+                .map(|&(thread, mask)| Event::SetAffinityMask { thread, mask }),
+        );
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn leaves_other_profiles_threads_to_them_when_reserving() {
+        capture_logs();
+        let game = Settings {
+            reserve_system: true,
+            ..soft()
+        };
+        let encoder = encoder("target=obs64.exe;threads=2;mode=hard");
+        let backend = MockBackend::new();
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script(&[(busy(), 45)]));
+        let clock = StopAfter {
+            clock: SpawnAfter {
+                clock: MockClock::new(&backend),
+                sleeps: Cell::new(20),
+                spawn: Box::new(|| {
+                    let activity = Frame::from_activity(&[(11, 100), (12, 90), (13, 1)]);
+                    backend.add_process(2000, "obs64.exe", 0xff, vec![activity; 40]);
+                }),
+            },
+            sleeps: Cell::new(55),
+            shutdown: Shutdown::new(),
+        };
+        let mut watcher = PollingWatcher::new(&backend, &clock).unwrap();
+        let profiles = [game, encoder];
+        let result = manage_targets(&backend, &clock, &mut watcher, &profiles, &clock.shutdown);
+        assert!(result.is_ok());
+        assert!(logged("Process for profile 'default' exited."));
+        let encoder_events: Vec<_> = backend
+            .events()
+            .into_iter()
+            .filter(|event| match *event {
+                Event::SetAffinityMask { thread, .. } => thread > 10,
+                _ => false,
+            })
+            .collect();
+        // The encoder is moved as it starts, given its CPUs back as soon as its profile manages
+        // it, and only its own profile changes its threads from then on, even when the game
+        // exits and its reservation is restored.
+        let expected: Vec<_> = [(11, 0b1101_0101), (12, 0b1101_0101), (13, 0b1101_0101)]
+            .iter()
+            .chain(&[(11, 0xff), (12, 0xff), (13, 0xff)])
+            .chain(&[(11, 0b1), (12, 0b100)])
+            .chain(&[(11, 0xff), (12, 0xff)])
+            .map(|&(thread, mask)| Event::SetAffinityMask { thread, mask })
+            .collect();
+        assert_eq!(encoder_events, expected);
+    }
+
+    #[test]
+    fn keeps_managing_when_a_hot_thread_exits_while_being_placed() {
+        capture_logs();
+        let backend = MockBackend::new();
+        let rest = Frame::from_activity(&[(1, 100), (3, 80), (4, 50), (5, 1)]);
+        let script = script(&[(busy(), 17), (rest, 20)]);
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script);
+        backend.exit_thread_when_changed(1000, 2);
+        let clock = MockClock::new(&backend);
+        let result = manage_target(&backend, &clock, &soft(), &Shutdown::new());
+        match result {
+            Err(Error::ProcessExited(1000)) => {}
+            other => panic!("{:?}", other),
+        }
+        // Thread 1 is placed before thread 2 exits, and the hot threads are placed again once
+        // they settle without it.
+        let mut expected = vec![Event::SetIdealProcessor { thread: 1, processor: 1 }];
+        expected.extend(assignment(&[1, 3, 4]));
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn dry_run_reports_without_changing_anything() {
+        capture_logs();
+        let backend = MockBackend::new();
+        let script = script(&[(busy(), 17), (busy(), 1), (busy().perturb(2, 6), 1)]);
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script);
+        let dry_run = DryRun::new(backend.clone());
+        let clock = MockClock::new(&backend);
+        let result = manage_target(&dry_run, &clock, &soft(), &Shutdown::new());
+        assert!(result.is_err());
+        assert_eq!(backend.events(), vec![]);
+        assert!(logged("Dry run: would set the ideal processor of thread 3 to 5."));
+        assert!(logged("Correcting affinities."));
+        let corrections = MESSAGES.with(|m| {
+            m.borrow()
+                .iter()
+                .filter(|message| message.contains("thread 2 to 3"))
+                .count()
+        });
+        assert_eq!(corrections, 2);
+    }
+
+    #[test]
+    fn manages_several_targets_on_separate_cores() {
+        capture_logs();
+        let backend = MockBackend::new();
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script(&[(busy(), 20)]));
+        let encoder = Frame::from_activity(&[(11, 100), (12, 90), (13, 1)]);
+        backend.add_process(2000, "obs64.exe", 0xff, script(&[(encoder, 20)]));
+        let mut obs = Settings {
+            name: "encoder".to_owned(),
+            hot_threads: 2,
+            ..soft()
+        };
+        obs.set("target", "obs64.exe").unwrap();
+        let profiles = [soft(), obs];
+        let clock = StopAfter {
+            clock: MockClock::new(&backend),
+            sleeps: Cell::new(25),
+            shutdown: Shutdown::new(),
+        };
+        let mut watcher = PollingWatcher::new(&backend, &clock).unwrap();
+        let result = manage_targets(&backend, &clock, &mut watcher, &profiles, &clock.shutdown);
+        assert!(result.is_ok());
+        assert!(logged("Process for profile 'encoder' exited."));
+        let mut expected = assignment(&[1, 2, 3]);
+        expected.extend(
+            [(11, 0), (12, 2)]
+                .iter()
+                .map(|&(thread, processor)| Event::SetIdealProcessor { thread, processor }),
+        );
+        assert_eq!(backend.events(), expected);
+    }
+
+    /// Manages the game and an encoder together until shortly after both could have settled.
+    fn run_with_encoder(encoder: &Settings) -> (MockBackend, HcbResult<()>) {
+        capture_logs();
+        let backend = MockBackend::new();
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script(&[(busy(), 30)]));
+        let activity = Frame::from_activity(&[(11, 100), (12, 90), (13, 1)]);
+        backend.add_process(2000, "obs64.exe", 0xff, script(&[(activity, 30)]));
+        let profiles = [soft(), encoder.clone()];
+        let clock = StopAfter {
+            clock: MockClock::new(&backend),
+            sleeps: Cell::new(20),
+            shutdown: Shutdown::new(),
+        };
+        let mut watcher = PollingWatcher::new(&backend, &clock).unwrap();
+        let result = manage_targets(&backend, &clock, &mut watcher, &profiles, &clock.shutdown);
+        (backend, result)
+    }
+
+    fn encoder(settings: &str) -> Settings {
+        let mut encoder = Settings {
+            name: "encoder".to_owned(),
+            ..soft()
+        };
+        encoder.apply_profile(settings).unwrap();
+        encoder
+    }
+
+    #[test]
+    fn leaves_a_profile_unassigned_while_another_holds_its_cpus() {
+        let (backend, result) = run_with_encoder(&encoder("target=obs64.exe;threads=1;policy=3"));
+        assert!(result.is_ok());
+        assert!(logged("Leaving the hot threads of profile 'encoder' unassigned"));
+        assert!(!logged(".."));
+        let mut expected = assignment(&[1, 2, 3]);
+        expected.extend(restoration(&[1, 2, 3]));
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn keeps_managing_other_profiles_when_one_cannot_manage_its_process() {
+        let (backend, result) = run_with_encoder(&encoder("target=obs64.exe;threads=9"));
+        assert!(result.is_ok());
+        assert!(logged("Cannot manage process 2000 for profile 'encoder'"));
+        assert!(backend.events().starts_with(&assignment(&[1, 2, 3])));
+    }
+
+    #[test]
+    fn claims_the_cpus_rules_hold_threads_on() {
+        let (backend, result) =
+            run_with_encoder(&encoder("target=obs64.exe;threads=1;rule=share<5% -> 3"));
+        assert!(result.is_ok());
+        // The game's hot threads avoid the CPU the encoder's rule holds thread 13 on.
+        let events = backend.events();
+        assert_eq!(
+            events[..4].to_vec(),
+            vec![
+                Event::SetAffinityMask { thread: 13, mask: 0b1000 },
+                Event::SetIdealProcessor { thread: 1, processor: 1 },
+                Event::SetIdealProcessor { thread: 2, processor: 2 },
+                Event::SetIdealProcessor { thread: 3, processor: 5 },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_rules_on_cpus_another_profile_lists() {
+        let backend = MockBackend::new();
+        let profiles = [
+            Settings {
+                policy: "1,3,5".parse().unwrap(),
+                ..soft()
+            },
+            encoder("target=obs64.exe;rule=share<5% -> 5"),
+        ];
+        let clock = MockClock::new(&backend);
+        let mut watcher = PollingWatcher::new(&backend, &clock).unwrap();
+        let result = manage_targets(&backend, &clock, &mut watcher, &profiles, &Shutdown::new());
+        match result {
+            Err(Error::Config(ref e)) => assert!(e.contains("both assign CPUs 5"), "{}", e),
+            other => panic!("expected a configuration error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_profiles_listing_the_same_cpus() {
+        let backend = MockBackend::new();
+        let profiles = [
+            Settings {
+                policy: "1,3,5".parse().unwrap(),
+                ..soft()
+            },
+            encoder("target=obs64.exe;threads=2;policy=7,5"),
+        ];
+        let clock = MockClock::new(&backend);
+        let mut watcher = PollingWatcher::new(&backend, &clock).unwrap();
+        let result = manage_targets(&backend, &clock, &mut watcher, &profiles, &Shutdown::new());
+        match result {
+            Err(Error::Config(ref e)) if e.contains("both assign CPUs 5") => {}
+            other => panic!("expected the shared CPU to be rejected, got {:?}", other),
+        }
+    }
+
+    /// A watcher which launches the game on its third wait and reports it at once.
+    struct Launcher<'a> {
+        backend: MockBackend,
+        clock: &'a StopAfter,
+        waits: u32,
+    }
+
+    impl<'a> ProcessWatcher for Launcher<'a> {
+        fn wait(&mut self, timeout: Duration) -> HcbResult<Vec<ProcessEvent>> {
+            self.waits += 1;
+            if self.waits == 3 {
+                let script = script(&[(busy(), 20)]);
+                self.backend.add_process(1000, "RocketLeague.exe", 0xff, script);
+                return Ok(vec![ProcessEvent::Started(1000)]);
+            }
+            self.clock.sleep(timeout);
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn starts_managing_targets_as_they_start() {
+        capture_logs();
+        let backend = MockBackend::new();
+        let clock = StopAfter {
+            clock: MockClock::new(&backend),
+            sleeps: Cell::new(25),
+            shutdown: Shutdown::new(),
+        };
+        let mut watcher = Launcher {
+            backend: backend.clone(),
+            clock: &clock,
+            waits: 0,
+        };
+        let profiles = [soft()];
+        let result = manage_targets(&backend, &clock, &mut watcher, &profiles, &clock.shutdown);
+        assert!(result.is_ok());
+        assert_eq!(clock.clock.elapsed(), Duration::from_secs(25));
+        assert!(logged("Process 1000 matching 'RocketLeague.exe' found for profile 'default'."));
+        assert!(backend.events().starts_with(&assignment(&[1, 2, 3])));
+    }
+
+    #[test]
+    fn manages_only_the_launched_family_until_the_game_exits() {
+        capture_logs();
+        let backend = MockBackend::new();
+        backend.add_process(900, "RocketLeague.exe", 0xff, vec![]);
+        backend.add_process(500, "launcher", 0xff, vec![]);
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script(&[(busy(), 20)]));
+        backend.set_parent(1000, 500);
+        let clock = MockClock::new(&backend);
+        let mut watcher = PollingWatcher::new(&backend, &clock).unwrap();
+        let profiles = [soft()];
+        let family = Family::new(500);
+        let result =
+            manage_family(&backend, &clock, &mut watcher, &profiles, &Shutdown::new(), family);
+        assert!(result.is_ok());
+        assert!(logged("Process 1000 matching 'RocketLeague.exe' found for profile 'default'."));
+        assert!(!logged("Process 900"));
+        assert!(backend.events().starts_with(&assignment(&[1, 2, 3])));
+    }
+
+    #[test]
+    fn ranks_threads_across_descendant_processes() {
+        capture_logs();
+        let backend = MockBackend::new();
+        let game = Frame::from_activity(&[(1, 100), (4, 1), (5, 1)]);
+        let helper = Frame::from_activity(&[(2, 90), (3, 80)]);
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script(&[(game, 20)]));
+        backend.add_process(1001, "helper.exe", 0xff, script(&[(helper, 21)]));
+        backend.set_parent(1001, 1000);
+        let settings = Settings {
+            descendants: true,
+            ..soft()
+        };
+        let result = manage_target(&backend, &MockClock::new(&backend), &settings, &Shutdown::new());
+        match result {
+            Err(Error::ProcessExited(1000)) => {}
+            other => panic!("expected the process to exit, got {:?}", other),
+        }
+        assert!(logged("Monitoring descendant process 1001."));
+        let mut expected = assignment(&[1, 2, 3]);
+        expected.extend(restoration(&[2, 3]));
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn monitors_descendants_as_they_start() {
+        capture_logs();
+        let backend = MockBackend::new();
+        let game = Frame::from_activity(&[(1, 100), (4, 1), (5, 1)]);
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script(&[(game, 20)]));
+        backend.set_start_time(1000, 10);
+        let clock = SpawnAfter {
+            clock: MockClock::new(&backend),
+            sleeps: Cell::new(1),
+            spawn: Box::new(|| {
+                let helper = Frame::from_activity(&[(2, 90), (3, 80)]);
+                backend.add_process(1001, "helper.exe", 0xff, script(&[(helper, 20)]));
+                backend.set_parent(1001, 1000);
+                backend.set_start_time(1001, 20);
+                // Started before the game, so its parent was another process with the same id.
+                let stale = Frame::from_activity(&[(6, 95)]);
+                backend.add_process(1002, "stale.exe", 0xff, script(&[(stale, 20)]));
+                backend.set_parent(1002, 1000);
+                backend.set_start_time(1002, 5);
+            }),
+        };
+        let settings = Settings {
+            descendants: true,
+            ..soft()
+        };
+        let result = manage_target(&backend, &clock, &settings, &Shutdown::new());
+        assert!(result.is_err());
+        assert!(logged("Monitoring descendant process 1001."));
+        assert!(!logged("Monitoring descendant process 1002."));
+        assert!(backend.events().starts_with(&assignment(&[1, 2, 3])));
+    }
+
+    /// Manages the game for `sleeps` polls, reloading `profile` after the poll `at`.
+    fn run_reloading(at: u32, profile: Settings, sleeps: u32) -> MockBackend {
+        capture_logs();
+        let backend = MockBackend::new();
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script(&[(busy(), 30)]));
+        let clock = StopAfter {
+            clock: MockClock::new(&backend),
+            sleeps: Cell::new(sleeps),
+            shutdown: Shutdown::new(),
+        };
+        let mut watcher = PollingWatcher::new(&backend, &clock).unwrap();
+        let mut polls = 0;
+        let reload = || {
+            polls += 1;
+            if polls == at + 1 {
+                Some(vec![profile.clone()])
+            } else {
+                None
+            }
+        };
+        let profiles = [soft()];
+        let result = manage_reloading(
+            &backend,
+            &clock,
+            &mut watcher,
+            &profiles,
+            &clock.shutdown,
+            None,
+            reload,
+        );
+        assert!(result.is_ok());
+        backend
+    }
+
+    #[test]
+    fn applies_reloaded_timing_from_the_next_poll() {
+        let settings = Settings {
+            settling_period: Duration::from_secs(5),
+            ..soft()
+        };
+        let backend = run_reloading(1, settings, 8);
+        assert!(logged("Assigning thread affinities."));
+        assert!(backend.events().starts_with(&assignment(&[1, 2, 3])));
+    }
+
+    #[test]
+    fn reassigns_settled_threads_when_the_policy_is_reloaded() {
+        let settings = Settings {
+            policy: "6,2,4".parse().unwrap(),
+            ..soft()
+        };
+        let backend = run_reloading(20, settings, 25);
+        assert!(logged("Reassigning thread affinities for the new settings."));
+        let mut expected = assignment(&[1, 2, 3]);
+        expected.extend(restoration(&[1, 2, 3]));
+        expected.extend(
+            [(1, 6), (2, 2), (3, 4)]
+                .iter()
+                .map(|&(thread, processor)| Event::SetIdealProcessor { thread, processor }),
+        );
+        expected.extend(restoration(&[1, 2, 3]));
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn keeps_the_current_profiles_when_a_reload_is_invalid() {
+        let settings = Settings {
+            hot_threads: 9,
+            ..soft()
+        };
+        let backend = run_reloading(20, settings, 25);
+        assert!(logged(
+            "Keeping the current profiles: Profile 'default': Cannot manage 9 hot threads on 8 \
+             usable CPUs."
+        ));
+        assert!(!logged(".."));
+        let mut expected = assignment(&[1, 2, 3]);
+        expected.extend(restoration(&[1, 2, 3]));
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn keeps_the_current_profiles_when_a_reload_needs_claimed_cpus() {
+        capture_logs();
+        let backend = MockBackend::new();
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script(&[(busy(), 30)]));
+        let activity = Frame::from_activity(&[(11, 100), (12, 1)]);
+        backend.add_process(2000, "obs64.exe", 0xff, script(&[(activity, 30)]));
+        let encoder = encoder("target=obs64.exe;threads=1");
+        let profiles = [soft(), encoder.clone()];
+        let clock = StopAfter {
+            clock: MockClock::new(&backend),
+            sleeps: Cell::new(25),
+            shutdown: Shutdown::new(),
+        };
+        let mut watcher = PollingWatcher::new(&backend, &clock).unwrap();
+        let mut polls = 0;
+        // The encoder's hot thread holds CPU 0 by then.
+        let reload = || {
+            polls += 1;
+            if polls == 21 {
+                let game = Settings {
+                    policy: "0,2,4".parse().unwrap(),
+                    ..soft()
+                };
+                Some(vec![game, encoder.clone()])
+            } else {
+                None
+            }
+        };
+        let result = manage_reloading(
+            &backend,
+            &clock,
+            &mut watcher,
+            &profiles,
+            &clock.shutdown,
+            None,
+            reload,
+        );
+        assert!(result.is_ok());
+        assert!(logged("Keeping the current profiles: Profile 'default': "));
+        assert!(!logged("Reassigning thread affinities for the new settings."));
+        let mut expected = assignment(&[1, 2, 3]);
+        expected.push(Event::SetIdealProcessor {
+            thread: 11,
+            processor: 0,
+        });
+        assert!(backend.events().starts_with(&expected));
+    }
+
+    #[test]
+    fn keeps_the_current_profiles_when_a_waiting_profile_is_invalid() {
+        let settings = Settings {
+            target: Selector::name("other.exe"),
+            cores: 0b11,
+            ..soft()
+        };
+        let backend = run_reloading(20, settings, 25);
+        assert!(logged("Keeping the current profiles: Profile 'default': "));
+        let mut expected = assignment(&[1, 2, 3]);
+        expected.extend(restoration(&[1, 2, 3]));
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn holds_threads_matching_rules_apart_from_the_hot_threads() {
+        capture_logs();
+        let backend = MockBackend::new();
+        backend.add_process(1000, "RocketLeague.exe", 0xff, script(&[(busy(), 17)]));
+        backend.set_thread_name(1000, 1, "RenderThread");
+        let mut settings = Settings {
+            hot_threads: 2,
+            ..soft()
+        };
+        settings.set("rule", "name:Render* -> 0").unwrap();
+        let result = manage_target(&backend, &MockClock::new(&backend), &settings, &Shutdown::new());
+        assert!(result.is_err());
+        assert!(logged(
+            "Thread 1 (RenderThread) matches rule 'name:Render* -> 0', holding it on CPUs 0."
+        ));
+        let mut expected = vec![Event::SetAffinityMask { thread: 1, mask: 0b1 }];
+        expected.extend(assignment(&[2, 3]));
+        assert_eq!(backend.events(), expected);
+    }
+
+    #[test]
+    fn rejects_duplicate_profile_names() {
+        let backend = MockBackend::new();
+        let profiles = [soft(), soft()];
+        let clock = MockClock::new(&backend);
+        let mut watcher = PollingWatcher::new(&backend, &clock).unwrap();
+        let result = manage_targets(&backend, &clock, &mut watcher, &profiles, &Shutdown::new());
+        match result {
+            Err(Error::Config(_)) => {}
+            other => panic!("expected a configuration error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_evicting_from_every_cpu() {
+        capture_logs();
+        let settings = Settings {
+            evict: true,
+            hot_threads: 2,
+            ..soft()
+        };
+        let backend = MockBackend::new();
+        backend.add_process(1000, "RocketLeague.exe", 0b1010, script(&[(busy(), 1)]));
+        match manage_target(&backend, &MockClock::new(&backend), &settings, &Shutdown::new()) {
+            Err(Error::Policy(_)) => {}
+            other => panic!("expected a policy error, got {:?}", other),
+        }
+    }
+}
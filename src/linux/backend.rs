@@ -0,0 +1,121 @@
+use std::path::{Path, PathBuf};
+
+use backend::{Backend, CpuTime, OsProcess, OsThread};
+use linux::{cpu, topology, Process, Thread};
+use topology::Topology;
+use HcbResult;
+
+/// The Linux process and thread backend, built on `/proc` and `sched_setaffinity`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct LinuxBackend;
+
+impl Backend for LinuxBackend {
+    type Process = Process;
+
+    fn processes(&self) -> HcbResult<Vec<Process>> {
+        Ok(Process::all()?.collect())
+    }
+
+    fn process(&self, id: u32) -> HcbResult<Process> {
+        Ok(Process::from_id(id)?)
+    }
+
+    fn cpu_times(&self) -> HcbResult<Vec<CpuTime>> {
+        Ok(cpu::times()?)
+    }
+
+    fn topology(&self) -> HcbResult<Option<Topology>> {
+        Ok(Some(topology::discover(Path::new(topology::SYSFS_CPU))?))
+    }
+}
+
+impl OsProcess for Process {
+    type Thread = Thread;
+
+    fn id(&self) -> u32 {
+        Process::id(self)
+    }
+
+    fn running(&self) -> bool {
+        Process::running(self)
+    }
+
+    fn name(&self) -> HcbResult<String> {
+        Ok(Process::name(self)?)
+    }
+
+    fn path(&self) -> HcbResult<PathBuf> {
+        Ok(Process::path(self)?)
+    }
+
+    fn command_line(&self) -> HcbResult<String> {
+        Ok(Process::command_line(self)?)
+    }
+
+    fn parent_id(&self) -> HcbResult<u32> {
+        Ok(Process::parent_id(self)?)
+    }
+
+    fn start_time(&self) -> HcbResult<u64> {
+        Ok(Process::start_time(self))
+    }
+
+    /// The real user id, or `None` for kernel threads.
+    fn owner(&self) -> HcbResult<Option<u32>> {
+        Ok(Process::owner(self)?)
+    }
+
+    fn environment_variable(&self, name: &str) -> HcbResult<Option<String>> {
+        Ok(Process::environment_variable(self, name)?)
+    }
+
+    fn affinity_mask(&self) -> HcbResult<usize> {
+        Ok(Process::affinity_mask(self)?)
+    }
+
+    fn set_affinity_mask(&mut self, mask: usize) -> HcbResult<usize> {
+        Ok(Process::set_affinity_mask(self, mask)?)
+    }
+
+    fn thread_ids(&self) -> HcbResult<Vec<u32>> {
+        Ok(Process::thread_ids(self)?.collect())
+    }
+
+    fn thread(&self, id: u32) -> HcbResult<Thread> {
+        Ok(Process::thread(self, id)?)
+    }
+}
+
+impl OsThread for Thread {
+    fn id(&self) -> u32 {
+        Thread::id(self)
+    }
+
+    fn name(&self) -> HcbResult<Option<String>> {
+        Ok(Some(Thread::name(self)?))
+    }
+
+    fn cpu_counter(&self) -> HcbResult<u64> {
+        Ok(self.cpu_time()?)
+    }
+
+    fn ideal_processor(&self) -> HcbResult<u32> {
+        Ok(Thread::ideal_processor(self)?)
+    }
+
+    fn set_ideal_processor(&mut self, processor: u32) -> HcbResult<u32> {
+        Ok(Thread::set_ideal_processor(self, processor)?)
+    }
+
+    fn emulates_ideal_processor(&self) -> bool {
+        true
+    }
+
+    fn affinity_mask(&self) -> HcbResult<usize> {
+        Ok(Thread::affinity_mask(self)?)
+    }
+
+    fn set_affinity_mask(&mut self, mask: usize) -> HcbResult<usize> {
+        Ok(Thread::set_affinity_mask(self, mask)?)
+    }
+}
@@ -0,0 +1,67 @@
+use std::fs;
+use std::io;
+
+use backend::CpuTime;
+
+/// Reads the cumulative time of each logical CPU from `/proc/stat`, in clock ticks.
+pub fn times() -> io::Result<Vec<CpuTime>> {
+    Ok(parse_times(&fs::read_to_string("/proc/stat")?))
+}
+
+fn parse_times(stat: &str) -> Vec<CpuTime> {
+    let mut times = Vec::new();
+    for line in stat.lines() {
+        let mut fields = line.split_whitespace();
+        let id = match fields.next().and_then(|name| name.strip_prefix("cpu")) {
+            Some(id) => match id.parse::<usize>() {
+                Ok(id) => id,
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+        // user nice system idle iowait irq softirq steal
+        let values: Vec<u64> = fields.take(8).filter_map(|v| v.parse().ok()).collect();
+        let total = values.iter().sum();
+        let idle = values.get(3).cloned().unwrap_or(0) + values.get(4).cloned().unwrap_or(0);
+        if times.len() <= id {
+            times.resize(id + 1, CpuTime::default());
+        }
+        times[id] = CpuTime {
+            busy: total - idle,
+            total,
+        };
+    }
+    times
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_per_cpu_lines() {
+        let stat = "cpu  30 0 30 200 10 0 0 0 0 0\n\
+                    cpu0 10 0 20 100 5 0 0 0 0 0\n\
+                    cpu1 20 0 10 100 5 0 0 0 0 0\n\
+                    intr 12345\n";
+        let times = parse_times(stat);
+        assert_eq!(
+            times,
+            vec![
+                CpuTime {
+                    busy: 30,
+                    total: 135,
+                },
+                CpuTime {
+                    busy: 30,
+                    total: 135,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_proc_stat() {
+        assert!(!times().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,9 @@
+mod backend;
+pub mod cpu;
+mod process;
+pub mod reload;
+pub mod topology;
+pub mod watch;
+
+pub use self::backend::LinuxBackend;
+pub use self::process::{Process, Thread};
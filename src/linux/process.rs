@@ -0,0 +1,454 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use libc;
+
+/// The `PF_KTHREAD` process flag, set for kernel threads.
+const PF_KTHREAD: u64 = 0x0020_0000;
+
+/// Returns the calling thread's last OS error, treating a missing task as not found.
+fn last_os_error() -> io::Error {
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::ESRCH) => io::Error::new(ErrorKind::NotFound, err),
+        _ => err,
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Parses the fields following the command name in a `/proc/.../stat` file.
+///
+/// The command name is wrapped in parentheses and may itself contain spaces or parentheses, so
+/// the remaining fields start after the last closing parenthesis. The first returned field is
+/// the state, field 3 in `proc(5)`.
+fn stat_fields(stat: &str) -> io::Result<Vec<&str>> {
+    stat.rfind(')')
+        .map(|end| stat[end + 1..].split_whitespace().collect())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "malformed stat file"))
+}
+
+/// Parses a numeric field of a stat file, where `field` is numbered as in `proc(5)`.
+fn stat_field(fields: &[&str], field: usize) -> io::Result<u64> {
+    fields
+        .get(field - 3)
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "malformed stat file"))
+}
+
+/// Returns true if the executable is one of Wine's loaders, which run Windows programs.
+fn is_wine_loader(exe: &Path) -> bool {
+    match exe.file_name().and_then(|name| name.to_str()) {
+        Some(name) => ["wine", "wine64", "wine-preloader", "wine64-preloader"].contains(&name),
+        None => false,
+    }
+}
+
+/// Returns the file name of the first Windows executable among the arguments, such as
+/// `RocketLeague.exe` for `Z:\Games\rocketleague\Binaries\Win64\RocketLeague.exe`.
+fn windows_executable<'a, I: IntoIterator<Item = &'a str>>(args: I) -> Option<String> {
+    args.into_iter()
+        .find(|arg| arg.to_ascii_lowercase().ends_with(".exe"))
+        .and_then(|arg| arg.rsplit(&['\\', '/'][..]).next())
+        .map(str::to_owned)
+}
+
+/// Returns true if the arguments are those of a Wine process, whose program is either Wine's
+/// loader or, once Wine has rewritten them, the Windows executable itself.
+fn wine_args(args: &[String]) -> bool {
+    args.first().is_some_and(|program| {
+        is_wine_loader(Path::new(program)) || program.to_ascii_lowercase().ends_with(".exe")
+    })
+}
+
+fn get_affinity(tid: u32) -> io::Result<usize> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        let ret = libc::sched_getaffinity(
+            tid as libc::pid_t,
+            mem::size_of::<libc::cpu_set_t>(),
+            &mut set,
+        );
+        if ret != 0 {
+            return Err(last_os_error());
+        }
+        Ok((0..mem::size_of::<usize>() * 8)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .fold(0, |mask, cpu| mask | 1 << cpu))
+    }
+}
+
+fn set_affinity(tid: u32, mask: usize) -> io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = mem::zeroed();
+        for cpu in (0..mem::size_of::<usize>() * 8).filter(|cpu| mask & 1 << cpu != 0) {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        let ret = libc::sched_setaffinity(
+            tid as libc::pid_t,
+            mem::size_of::<libc::cpu_set_t>(),
+            &set,
+        );
+        if ret != 0 {
+            Err(last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Process {
+    id: u32,
+    start_time: u64,
+}
+
+impl Process {
+    /// Creates a process handle from a PID.
+    pub fn from_id(id: u32) -> io::Result<Process> {
+        let start_time = Process::read_start_time(id)?;
+        Ok(Process { id, start_time })
+    }
+
+    /// Enumerates all running processes.
+    pub fn all() -> io::Result<impl Iterator<Item = Process>> {
+        Ok(fs::read_dir("/proc")?
+            .filter_map(Result::ok)
+            .filter_map(|entry| entry.file_name().to_str().and_then(|name| name.parse().ok()))
+            .filter_map(|id| Process::from_id(id).ok()))
+    }
+
+    fn read_start_time(id: u32) -> io::Result<u64> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", id))?;
+        stat_field(&stat_fields(&stat)?, 22)
+    }
+
+    /// Returns the process's id.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns when the process started, in clock ticks since boot.
+    pub fn start_time(&self) -> u64 {
+        self.start_time
+    }
+
+    /// Returns true if the process is running.
+    ///
+    /// A zombie, or a new process which has reused the id, is not considered running.
+    pub fn running(&self) -> bool {
+        fs::read_to_string(format!("/proc/{}/stat", self.id))
+            .ok()
+            .and_then(|stat| {
+                let fields = stat_fields(&stat).ok()?;
+                let start_time = stat_field(&fields, 22).ok()?;
+                Some(fields[0] != "Z" && start_time == self.start_time)
+            })
+            .unwrap_or(false)
+    }
+
+    /// Returns the path of the executable of the process.
+    pub fn path(&self) -> io::Result<PathBuf> {
+        fs::read_link(format!("/proc/{}/exe", self.id))
+    }
+
+    /// Returns the unqualified name of the executable of the process.
+    ///
+    /// For a Windows program run by Wine, such as a game under Proton, this is the name of the
+    /// Windows executable from the command line rather than that of Wine's loader.
+    ///
+    /// When the executable cannot be read, such as for processes owned by other users, Wine
+    /// processes are recognised from their command line, which anyone can read. Other processes
+    /// fall back to the kernel's command name, which is truncated to 15 bytes.
+    pub fn name(&self) -> io::Result<String> {
+        match self.path() {
+            Ok(ref path) if is_wine_loader(path) => {
+                let args = self.args()?;
+                match windows_executable(args.iter().map(String::as_str)) {
+                    Some(name) => Ok(name),
+                    None => Ok(file_name(path)),
+                }
+            }
+            Ok(path) => Ok(file_name(&path)),
+            Err(_) => {
+                let args = self.args()?;
+                match windows_executable(args.iter().map(String::as_str)) {
+                    Some(name) if wine_args(&args) => Ok(name),
+                    _ => Ok(fs::read_to_string(format!("/proc/{}/comm", self.id))?
+                        .trim_end()
+                        .to_owned()),
+                }
+            }
+        }
+    }
+
+    /// Returns the arguments the process was started with, including the program.
+    fn args(&self) -> io::Result<Vec<String>> {
+        let cmdline = fs::read(format!("/proc/{}/cmdline", self.id))?;
+        Ok(cmdline
+            .split(|&b| b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect())
+    }
+
+    /// Returns the command line of the process, with arguments separated by spaces.
+    ///
+    /// Kernel threads have an empty command line.
+    pub fn command_line(&self) -> io::Result<String> {
+        Ok(self.args()?.join(" "))
+    }
+
+    /// Returns the value of an environment variable of the process, as it was when the process
+    /// started.
+    pub fn environment_variable(&self, name: &str) -> io::Result<Option<String>> {
+        let environ = fs::read(format!("/proc/{}/environ", self.id))?;
+        Ok(environ
+            .split(|&b| b == 0)
+            .find(|var| {
+                var.len() > name.len() && var.starts_with(name.as_bytes())
+                    && var[name.len()] == b'='
+            })
+            .map(|var| String::from_utf8_lossy(&var[name.len() + 1..]).into_owned()))
+    }
+
+    /// Returns the real user id of the process, or `None` for a kernel thread, which belongs to
+    /// no user.
+    pub fn owner(&self) -> io::Result<Option<u32>> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", self.id))?;
+        if stat_field(&stat_fields(&stat)?, 9)? & PF_KTHREAD != 0 {
+            return Ok(None);
+        }
+        let status = fs::read_to_string(format!("/proc/{}/status", self.id))?;
+        status
+            .lines()
+            .filter_map(|line| line.strip_prefix("Uid:"))
+            .filter_map(|ids| ids.split_whitespace().next())
+            .filter_map(|uid| uid.parse().ok())
+            .next()
+            .map(Some)
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "malformed status file"))
+    }
+
+    /// Returns the id of the process's parent, or 0 if it has none.
+    pub fn parent_id(&self) -> io::Result<u32> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", self.id))?;
+        Ok(stat_field(&stat_fields(&stat)?, 4)? as u32)
+    }
+
+    /// Returns the affinity mask of the process's main thread.
+    pub fn affinity_mask(&self) -> io::Result<usize> {
+        get_affinity(self.id)
+    }
+
+    /// Sets the affinity mask of every thread of the process, returning the main thread's
+    /// previous mask.
+    ///
+    /// Threads which exit while this is in progress are skipped.
+    pub fn set_affinity_mask(&mut self, mask: usize) -> io::Result<usize> {
+        let prev = get_affinity(self.id)?;
+        for tid in self.thread_ids()? {
+            match set_affinity(tid, mask) {
+                Err(ref err) if err.kind() == ErrorKind::NotFound && tid != self.id => {}
+                other => other?,
+            }
+        }
+        Ok(prev)
+    }
+
+    pub fn thread_ids(&self) -> io::Result<impl Iterator<Item = u32>> {
+        Ok(fs::read_dir(format!("/proc/{}/task", self.id))?
+            .filter_map(Result::ok)
+            .filter_map(|entry| entry.file_name().to_str().and_then(|name| name.parse().ok())))
+    }
+
+    pub fn thread(&self, id: u32) -> io::Result<Thread> {
+        Thread::from_ids(self.id, id)
+    }
+}
+
+#[derive(Debug)]
+pub struct Thread {
+    process_id: u32,
+    id: u32,
+}
+
+impl Thread {
+    /// Creates a thread handle from a PID and a thread ID.
+    pub fn from_ids(process_id: u32, id: u32) -> io::Result<Thread> {
+        fs::metadata(format!("/proc/{}/task/{}", process_id, id))?;
+        Ok(Thread { process_id, id })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the id of the process the thread belongs to.
+    pub fn process_id(&self) -> u32 {
+        self.process_id
+    }
+
+    fn stat(&self) -> io::Result<String> {
+        fs::read_to_string(format!("/proc/{}/task/{}/stat", self.process_id, self.id))
+    }
+
+    /// Returns the thread's name, which the kernel truncates to 15 bytes.
+    pub fn name(&self) -> io::Result<String> {
+        let path = format!("/proc/{}/task/{}/comm", self.process_id, self.id);
+        Ok(fs::read_to_string(path)?.trim_end_matches('\n').to_owned())
+    }
+
+    /// Returns the user plus system time of the thread, in clock ticks.
+    pub fn cpu_time(&self) -> io::Result<u64> {
+        let stat = self.stat()?;
+        let fields = stat_fields(&stat)?;
+        Ok(stat_field(&fields, 14)? + stat_field(&fields, 15)?)
+    }
+
+    /// Gets the CPU the thread is pinned to.
+    ///
+    /// Linux has no ideal processors, so `set_ideal_processor` pins the thread to the CPU
+    /// instead. This is the lowest CPU of the thread's affinity mask, which is that CPU for as
+    /// long as the thread stays pinned.
+    pub fn ideal_processor(&self) -> io::Result<u32> {
+        Ok(self.affinity_mask()?.trailing_zeros())
+    }
+
+    /// Pins the thread to a CPU, which is the closest Linux has to an ideal processor, so this
+    /// is the same as hard placement. On success, returns the previous pinned CPU.
+    ///
+    /// Fails with `InvalidInput` for CPUs beyond those an affinity mask can hold.
+    pub fn set_ideal_processor(&mut self, processor: u32) -> io::Result<u32> {
+        if processor >= usize::BITS {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("CPU {} is beyond the affinity mask", processor),
+            ));
+        }
+        let prev = self.ideal_processor()?;
+        set_affinity(self.id, 1 << processor)?;
+        Ok(prev)
+    }
+
+    /// Returns the affinity mask of the thread.
+    pub fn affinity_mask(&self) -> io::Result<usize> {
+        get_affinity(self.id)
+    }
+
+    /// Sets the affinity of the thread. On success, returns the previous affinity mask.
+    pub fn set_affinity_mask(&mut self, mask: usize) -> io::Result<usize> {
+        let prev = self.affinity_mask()?;
+        set_affinity(self.id, mask)?;
+        Ok(prev)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn current() -> Process {
+        Process::from_id(process::id()).unwrap()
+    }
+
+    #[test]
+    fn enumerates_processes() {
+        let procs: Vec<_> = Process::all().unwrap().collect();
+        assert!(procs.iter().any(|p| p.id() == process::id()));
+    }
+
+    #[test]
+    fn parses_stat_with_awkward_command_names() {
+        let fields = stat_fields("42 (a) b (c)) S 1 42 42 0 -1 4194560 100 0 0 0 7 3").unwrap();
+        assert_eq!(fields[0], "S");
+        assert_eq!(stat_field(&fields, 14).unwrap(), 7);
+        assert_eq!(stat_field(&fields, 15).unwrap(), 3);
+    }
+
+    #[test]
+    fn reads_command_line() {
+        let command_line = current().command_line().unwrap();
+        let exe = env::args().next().unwrap();
+        assert!(command_line.starts_with(&exe));
+    }
+
+    #[test]
+    fn names_wine_processes_after_the_windows_executable() {
+        assert!(is_wine_loader(Path::new("/usr/lib/wine/wine64-preloader")));
+        assert!(!is_wine_loader(Path::new("/usr/bin/winecfg")));
+        let proton = [
+            "Z:\\Games\\rocketleague\\Binaries\\Win64\\RocketLeague.exe",
+            "-nomovie",
+        ];
+        assert_eq!(windows_executable(proton.iter().cloned()).unwrap(), "RocketLeague.exe");
+        let loader = ["/usr/bin/wine64", "C:/Program Files/Game/GAME.EXE"];
+        assert_eq!(windows_executable(loader.iter().cloned()).unwrap(), "GAME.EXE");
+        assert_eq!(windows_executable(vec!["/usr/bin/wineserver"]), None);
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert!(wine_args(&args(&proton)));
+        assert!(wine_args(&args(&loader)));
+        assert!(!wine_args(&args(&["/usr/bin/7z", "x", "setup.exe"])));
+    }
+
+    #[test]
+    fn reads_environment_variables() {
+        let path = env::var("PATH").ok();
+        assert_eq!(current().environment_variable("PATH").unwrap(), path);
+        assert_eq!(current().environment_variable("PAT").unwrap(), None);
+    }
+
+    #[test]
+    fn reads_parent_id() {
+        use std::os::unix::process::parent_id;
+        assert_eq!(current().parent_id().unwrap(), parent_id());
+    }
+
+    #[test]
+    fn enumerates_threads() {
+        let process = current();
+        assert!(process.running());
+        let ids: Vec<_> = process.thread_ids().unwrap().collect();
+        assert!(ids.contains(&process::id()));
+        assert!(
+            ids.iter()
+                .filter_map(|&id| process.thread(id).ok())
+                .any(|thread| thread.cpu_time().is_ok())
+        );
+    }
+
+    #[test]
+    fn reads_thread_names() {
+        let named = ::std::thread::Builder::new()
+            .name("rlhcb-named".to_owned())
+            .spawn(|| {
+                let tid = unsafe { libc::syscall(libc::SYS_gettid) } as u32;
+                current().thread(tid).unwrap().name().unwrap()
+            })
+            .unwrap();
+        assert_eq!(named.join().unwrap(), "rlhcb-named");
+    }
+
+    #[test]
+    fn round_trips_affinity() {
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) } as u32;
+        let mut thread = current().thread(tid).unwrap();
+        let original = thread.affinity_mask().unwrap();
+        let lowest = original.trailing_zeros();
+        thread.set_ideal_processor(lowest).unwrap();
+        assert_eq!(thread.ideal_processor().unwrap(), lowest);
+        assert_eq!(thread.set_affinity_mask(original).unwrap(), 1 << lowest);
+        assert_eq!(thread.affinity_mask().unwrap(), original);
+        let err = thread.set_ideal_processor(usize::BITS).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        assert_eq!(thread.affinity_mask().unwrap(), original);
+    }
+}
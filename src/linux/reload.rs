@@ -0,0 +1,167 @@
+//! Noticing changes to the configuration file through inotify.
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+
+use libc;
+
+use reload::{FileWatcher, ModifiedWatcher};
+use HcbResult;
+
+/// The size of `struct inotify_event` without the name which follows it.
+const EVENT_HEADER_LEN: usize = 16;
+
+/// The events which mean the file may have new contents. Editors often save by writing a new
+/// file and renaming it over the old one, so the directory is watched rather than the file.
+const EVENTS: u32 =
+    libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_DELETE | libc::IN_MOVED_FROM;
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    buf.get(offset..offset + 4)
+        .map(|bytes| u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Returns true if any of the inotify events in `buf` names the file `name`.
+pub fn names_file(buf: &[u8], name: &[u8]) -> bool {
+    let mut offset = 0;
+    while let Some(len) = read_u32(buf, offset + 12) {
+        let start = offset + EVENT_HEADER_LEN;
+        let end = start + len as usize;
+        let event_name = match buf.get(start..end) {
+            Some(event_name) => event_name,
+            None => break,
+        };
+        // The name is padded with nul bytes.
+        let event_name = match event_name.iter().position(|&b| b == 0) {
+            Some(nul) => &event_name[..nul],
+            None => event_name,
+        };
+        if event_name == name {
+            return true;
+        }
+        offset = end;
+    }
+    false
+}
+
+/// Finds changes to a file from the inotify events of its directory.
+#[derive(Debug)]
+pub struct InotifyWatcher {
+    fd: RawFd,
+    name: Vec<u8>,
+}
+
+impl InotifyWatcher {
+    pub fn new(path: &Path) -> io::Result<InotifyWatcher> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "not a file path");
+        let name = path.file_name().ok_or_else(invalid)?.as_bytes().to_vec();
+        let directory = match path.parent() {
+            Some(directory) if directory != Path::new("") => directory.to_owned(),
+            _ => PathBuf::from("."),
+        };
+        let directory = CString::new(directory.as_os_str().as_bytes()).map_err(|_| invalid())?;
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Dropping the watcher closes the descriptor if adding the watch fails.
+        let watcher = InotifyWatcher { fd, name };
+        if unsafe { libc::inotify_add_watch(fd, directory.as_ptr(), EVENTS) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(watcher)
+    }
+}
+
+impl FileWatcher for InotifyWatcher {
+    fn changed(&mut self) -> HcbResult<bool> {
+        let mut changed = false;
+        let mut buf = [0u8; 4096];
+        loop {
+            let read = unsafe {
+                libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len())
+            };
+            if read < 0 {
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EAGAIN) | Some(libc::EINTR) => break,
+                    _ => return Err(err.into()),
+                }
+            } else if read == 0 {
+                break;
+            }
+            changed |= names_file(&buf[..read as usize], &self.name);
+        }
+        Ok(changed)
+    }
+}
+
+impl Drop for InotifyWatcher {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Returns an inotify watcher for the file at `path`, or a watcher which compares modification
+/// times if its directory cannot be watched, such as when it does not exist.
+pub fn watcher(path: &Path) -> HcbResult<Box<dyn FileWatcher>> {
+    match InotifyWatcher::new(path) {
+        Ok(watcher) => Ok(Box::new(watcher)),
+        Err(e) => {
+            info!(
+                "Cannot watch {} ({}), checking its modification time instead.",
+                path.display(),
+                e
+            );
+            Ok(Box::new(ModifiedWatcher::new(path)?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    fn event(name: &[u8]) -> Vec<u8> {
+        let len = (name.len() + 1).div_ceil(4) * 4;
+        let mut event = Vec::new();
+        for field in &[1, libc::IN_CLOSE_WRITE, 0, len as u32] {
+            event.extend_from_slice(&field.to_ne_bytes());
+        }
+        event.extend_from_slice(name);
+        event.resize(EVENT_HEADER_LEN + len, 0);
+        event
+    }
+
+    #[test]
+    fn finds_events_naming_the_file() {
+        let mut buf = event(b"config.toml.swp");
+        assert!(!names_file(&buf, b"config.toml"));
+        buf.extend(event(b"config.toml"));
+        assert!(names_file(&buf, b"config.toml"));
+    }
+
+    #[test]
+    fn notices_the_file_being_replaced() {
+        let directory = env::temp_dir().join(format!("rlhcbfix-inotify-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("config.toml");
+        let mut watcher = InotifyWatcher::new(&path).unwrap();
+        assert!(!watcher.changed().unwrap());
+        fs::write(directory.join("other.toml"), "").unwrap();
+        assert!(!watcher.changed().unwrap());
+        fs::write(directory.join("config.toml.new"), "poll = 2\n").unwrap();
+        fs::rename(directory.join("config.toml.new"), &path).unwrap();
+        assert!(watcher.changed().unwrap());
+        assert!(!watcher.changed().unwrap());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use topology::{self, Cpu, Topology};
+
+/// Where the kernel describes the CPUs.
+pub const SYSFS_CPU: &str = "/sys/devices/system/cpu";
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, format!("malformed {}", what))
+}
+
+fn read_u32(path: &Path) -> io::Result<u32> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|_| invalid(&path.display().to_string()))
+}
+
+/// Parses a kernel CPU list such as `0-3,8,10-11`.
+fn parse_cpu_list(list: &str) -> io::Result<Vec<u32>> {
+    topology::parse_cpu_list(list).ok_or_else(|| invalid("CPU list"))
+}
+
+/// Returns the lowest CPU sharing each cache level with the CPU at `cpu_dir`, which identifies
+/// the cache domain.
+fn cache_domains(cpu_dir: &Path) -> io::Result<HashMap<u32, u32>> {
+    let mut domains = HashMap::new();
+    let entries = match fs::read_dir(cpu_dir.join("cache")) {
+        Ok(entries) => entries,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(domains),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let index = entry?.path();
+        if !index
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with("index"))
+        {
+            continue;
+        }
+        if fs::read_to_string(index.join("type"))?.trim() == "Instruction" {
+            continue;
+        }
+        let level = read_u32(&index.join("level"))?;
+        let shared = parse_cpu_list(&fs::read_to_string(index.join("shared_cpu_list"))?)?;
+        if let Some(&lowest) = shared.iter().min() {
+            domains.insert(level, lowest);
+        }
+    }
+    Ok(domains)
+}
+
+fn numa_node(cpu_dir: &Path) -> io::Result<Option<u32>> {
+    for entry in fs::read_dir(cpu_dir)? {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        if let Some(Ok(node)) = name.strip_prefix("node").map(str::parse) {
+            return Ok(Some(node));
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the relative performance the kernel gives the CPU at `cpu_dir`, if it gives one.
+fn capacity(cpu_dir: &Path) -> io::Result<Option<u32>> {
+    match read_u32(&cpu_dir.join("cpu_capacity")) {
+        Ok(capacity) => Ok(Some(capacity)),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Returns the CPUs the kernel lists as the Atom cores of an Intel hybrid processor, from the
+/// `cpu_atom` device beside the CPUs under `root`.
+fn atom_cpus(root: &Path) -> io::Result<Vec<u32>> {
+    let list = match root.parent().and_then(Path::parent) {
+        Some(devices) => devices.join("cpu_atom/cpus"),
+        None => return Ok(Vec::new()),
+    };
+    match fs::read_to_string(list) {
+        Ok(list) => parse_cpu_list(&list),
+        Err(ref e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Discovers the topology of the online CPUs described under `root`, which is normally
+/// [`SYSFS_CPU`](constant.SYSFS_CPU.html) but may point at a copy of it.
+///
+/// Cache domains are identified by the lowest CPU sharing the cache. CPUs without cache
+/// information are given a private L2 and share an L3 with their package. CPUs with less than
+/// the highest capacity, or else those listed as Atom cores, are efficiency cores.
+pub fn discover(root: &Path) -> io::Result<Topology> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let id: u32 = match name.strip_prefix("cpu").map(str::parse) {
+            Some(Ok(id)) => id,
+            _ => continue,
+        };
+        let dir = entry.path();
+        // Offline CPUs have no topology directory.
+        if !dir.join("topology").is_dir() {
+            continue;
+        }
+        let package = read_u32(&dir.join("topology/physical_package_id"))?;
+        let core_id = read_u32(&dir.join("topology/core_id"))?;
+        let caches = cache_domains(&dir)?;
+        found.push((id, package, core_id, caches, numa_node(&dir)?, capacity(&dir)?));
+    }
+    found.sort_by_key(|&(id, ..)| id);
+    let highest = found.iter().filter_map(|&(.., capacity)| capacity).max();
+    let slower = |capacity: Option<u32>| match (capacity, highest) {
+        (Some(capacity), Some(highest)) => capacity < highest,
+        _ => false,
+    };
+    let atoms = if found.iter().any(|&(.., capacity)| slower(capacity)) {
+        Vec::new()
+    } else {
+        atom_cpus(root)?
+    };
+
+    // Core ids are only unique within a package, so number the cores in order of appearance.
+    let mut cores = HashMap::new();
+    let cpus = found
+        .into_iter()
+        .map(|(id, package, core_id, caches, node, capacity)| {
+            let next = cores.len() as u32;
+            let core = *cores.entry((package, core_id)).or_insert(next);
+            Cpu {
+                id,
+                core,
+                package,
+                l2: caches.get(&2).cloned().unwrap_or(id),
+                l3: caches.get(&3).cloned().unwrap_or(package),
+                node: node.unwrap_or(0),
+                efficiency: slower(capacity) || atoms.contains(&id),
+            }
+        })
+        .collect();
+    Ok(Topology::new(cpus))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    /// Writes a sysfs-like fixture of two packages with two SMT cores each, siblings numbered N
+    /// and N + 4, a shared L2 per core and an L3 and NUMA node per package. The second package
+    /// has half the capacity of the first.
+    fn fixture(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("rlhcbfix-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for id in 0..8u32 {
+            let dir = root.join(format!("cpu{}", id));
+            let (package, core) = (id % 4 / 2, id % 2);
+            let siblings = format!("{},{}", id % 4, id % 4 + 4);
+            let l3 = format!(
+                "{}-{},{}-{}",
+                package * 2,
+                package * 2 + 1,
+                package * 2 + 4,
+                package * 2 + 5
+            );
+            fs::create_dir_all(dir.join("topology")).unwrap();
+            fs::create_dir_all(dir.join(format!("node{}", package))).unwrap();
+            fs::write(
+                dir.join("topology/physical_package_id"),
+                format!("{}\n", package),
+            )
+            .unwrap();
+            fs::write(dir.join("topology/core_id"), format!("{}\n", core)).unwrap();
+            fs::write(dir.join("cpu_capacity"), format!("{}\n", 1024 >> package)).unwrap();
+            let caches = [
+                (1, "Data", &siblings),
+                (1, "Instruction", &siblings),
+                (2, "Unified", &siblings),
+                (3, "Unified", &l3),
+            ];
+            for (index, &(level, kind, shared)) in caches.iter().enumerate() {
+                let dir = dir.join(format!("cache/index{}", index));
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(dir.join("level"), format!("{}\n", level)).unwrap();
+                fs::write(dir.join("type"), format!("{}\n", kind)).unwrap();
+                fs::write(dir.join("shared_cpu_list"), format!("{}\n", shared)).unwrap();
+            }
+        }
+        // An offline CPU and unrelated entries are ignored.
+        fs::create_dir_all(root.join("cpu8")).unwrap();
+        fs::create_dir_all(root.join("cpufreq")).unwrap();
+        fs::write(root.join("online"), "0-7\n").unwrap();
+        root
+    }
+
+    #[test]
+    fn parses_cpu_lists() {
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11\n").unwrap(),
+            vec![0, 1, 2, 3, 8, 10, 11]
+        );
+        assert_eq!(parse_cpu_list("").unwrap(), vec![]);
+        assert!(parse_cpu_list("0-").is_err());
+    }
+
+    #[test]
+    fn discovers_fixture_topology() {
+        let root = fixture("topology");
+        let topology = discover(&root).unwrap();
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(topology.cpus().len(), 8);
+        assert_eq!(
+            topology.cores(),
+            vec![vec![0, 4], vec![1, 5], vec![2, 6], vec![3, 7]]
+        );
+        assert_eq!(
+            topology.cpu(6),
+            Some(&Cpu {
+                id: 6,
+                core: 2,
+                package: 1,
+                l2: 2,
+                l3: 2,
+                node: 1,
+                efficiency: true,
+            })
+        );
+    }
+
+    #[test]
+    fn discovers_this_machine() {
+        if Path::new(SYSFS_CPU).join("cpu0/topology").is_dir() {
+            assert!(!discover(Path::new(SYSFS_CPU)).unwrap().cpus().is_empty());
+        }
+    }
+}
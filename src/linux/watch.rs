@@ -0,0 +1,323 @@
+//! Process start and exit events from the kernel's process connector, with a `/proc` scanning
+//! fallback for when the connector is unavailable.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::mem;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use libc;
+
+use watch::{diff_identities, ProcessEvent, ProcessWatcher};
+use HcbResult;
+
+const CN_IDX_PROC: u32 = 1;
+const CN_VAL_PROC: u32 = 1;
+const PROC_CN_MCAST_LISTEN: u32 = 1;
+const PROC_CN_MCAST_IGNORE: u32 = 2;
+const PROC_EVENT_EXEC: u32 = 0x0000_0002;
+const PROC_EVENT_EXIT: u32 = 0x8000_0000;
+
+const NLMSG_HDRLEN: usize = 16;
+const CN_MSG_LEN: usize = 20;
+/// Offset of the event specific data within a `proc_event`, after `what`, `cpu` and the
+/// timestamp.
+const PROC_EVENT_DATA: usize = 16;
+
+fn read_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    buf.get(offset..offset + 4).map(|bytes| {
+        let mut word = [0; 4];
+        word.copy_from_slice(bytes);
+        u32::from_ne_bytes(word)
+    })
+}
+
+/// Parses the netlink messages in one datagram from the process connector.
+///
+/// Only processes executing a new program and whole processes exiting are reported, since
+/// thread exits and forks which keep the parent's executable cannot change which processes a
+/// selector matches.
+pub fn parse_events(buf: &[u8]) -> Vec<ProcessEvent> {
+    let mut events = Vec::new();
+    let mut offset = 0;
+    while let Some(len) = read_u32(buf, offset) {
+        let len = len as usize;
+        if len < NLMSG_HDRLEN || offset + len > buf.len() {
+            break;
+        }
+        let message = &buf[offset..offset + len];
+        let event = NLMSG_HDRLEN + CN_MSG_LEN;
+        let data = event + PROC_EVENT_DATA;
+        if let (Some(what), Some(pid), Some(tgid)) = (
+            read_u32(message, event),
+            read_u32(message, data),
+            read_u32(message, data + 4),
+        ) {
+            match what {
+                PROC_EVENT_EXEC => events.push(ProcessEvent::Started(tgid)),
+                PROC_EVENT_EXIT if pid == tgid => events.push(ProcessEvent::Exited(tgid)),
+                _ => {}
+            }
+        }
+        // Netlink messages are aligned to four bytes.
+        offset += (len + 3) & !3;
+    }
+    events
+}
+
+/// Receives process events from the kernel as they happen.
+///
+/// Subscribing to the process connector needs `CAP_NET_ADMIN`.
+#[derive(Debug)]
+pub struct ConnectorWatcher {
+    socket: RawFd,
+}
+
+impl ConnectorWatcher {
+    /// Subscribes to the process connector.
+    pub fn new() -> io::Result<ConnectorWatcher> {
+        let socket = unsafe {
+            libc::socket(
+                libc::AF_NETLINK,
+                libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+                libc::NETLINK_CONNECTOR,
+            )
+        };
+        if socket < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Dropping the watcher closes the socket if subscribing fails.
+        let watcher = ConnectorWatcher { socket };
+        let mut address: libc::sockaddr_nl = unsafe { mem::zeroed() };
+        address.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+        address.nl_groups = CN_IDX_PROC;
+        let ret = unsafe {
+            libc::bind(
+                socket,
+                &address as *const libc::sockaddr_nl as *const libc::sockaddr,
+                mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        watcher.send_op(PROC_CN_MCAST_LISTEN)?;
+        Ok(watcher)
+    }
+
+    fn send_op(&self, op: u32) -> io::Result<()> {
+        let len = NLMSG_HDRLEN + CN_MSG_LEN + 4;
+        let mut message = Vec::with_capacity(len);
+        // struct nlmsghdr
+        message.extend_from_slice(&(len as u32).to_ne_bytes());
+        message.extend_from_slice(&(libc::NLMSG_DONE as u16).to_ne_bytes());
+        message.extend_from_slice(&0u16.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(&unsafe { libc::getpid() as u32 }.to_ne_bytes());
+        // struct cn_msg
+        message.extend_from_slice(&CN_IDX_PROC.to_ne_bytes());
+        message.extend_from_slice(&CN_VAL_PROC.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(&0u32.to_ne_bytes());
+        message.extend_from_slice(&4u16.to_ne_bytes());
+        message.extend_from_slice(&0u16.to_ne_bytes());
+        // enum proc_cn_mcast_op
+        message.extend_from_slice(&op.to_ne_bytes());
+        let sent = unsafe {
+            libc::send(
+                self.socket,
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+            )
+        };
+        if sent < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Waits until the socket is readable or the timeout passes, returning true if readable.
+    fn readable(&self, timeout: Duration) -> io::Result<bool> {
+        let mut poll = libc::pollfd {
+            fd: self.socket,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let millis =
+            timeout.as_secs() * 1000 + u64::from(timeout.subsec_nanos()).div_ceil(1_000_000);
+        let millis = millis.min(libc::c_int::MAX as u64) as libc::c_int;
+        match unsafe { libc::poll(&mut poll, 1, millis) } {
+            -1 => {
+                let err = io::Error::last_os_error();
+                if err.kind() == ErrorKind::Interrupted {
+                    Ok(false)
+                } else {
+                    Err(err)
+                }
+            }
+            0 => Ok(false),
+            _ => Ok(true),
+        }
+    }
+}
+
+impl ProcessWatcher for ConnectorWatcher {
+    fn wait(&mut self, timeout: Duration) -> HcbResult<Vec<ProcessEvent>> {
+        let mut events = Vec::new();
+        if !self.readable(timeout)? {
+            return Ok(events);
+        }
+        let mut buf = [0u8; 4096];
+        loop {
+            let received = unsafe {
+                libc::recv(
+                    self.socket,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    libc::MSG_DONTWAIT,
+                )
+            };
+            if received < 0 {
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(libc::EAGAIN) | Some(libc::EINTR) => break,
+                    Some(libc::ENOBUFS) => events.push(ProcessEvent::Overflow),
+                    _ => return Err(err.into()),
+                }
+            } else {
+                events.extend(parse_events(&buf[..received as usize]));
+            }
+        }
+        Ok(events)
+    }
+}
+
+impl Drop for ConnectorWatcher {
+    fn drop(&mut self) {
+        let _ = self.send_op(PROC_CN_MCAST_IGNORE);
+        unsafe {
+            libc::close(self.socket);
+        }
+    }
+}
+
+/// What identifies the program a process runs: its start time, command name and executable.
+///
+/// The executable cannot be read for other users' processes, but the command name always can.
+type Identity = (u64, String, Option<PathBuf>);
+
+/// Finds process events by scanning `/proc` once per wait, unless no events are needed.
+///
+/// A process is reported as started when its id first appears, and again whenever it executes
+/// a new program, such as a launcher executing the game or a process first seen between forking
+/// and executing. The whole timeout is always waited.
+#[derive(Debug)]
+pub struct ProcScanWatcher {
+    known: HashMap<u32, Identity>,
+    needed: bool,
+}
+
+impl ProcScanWatcher {
+    pub fn new() -> io::Result<ProcScanWatcher> {
+        Ok(ProcScanWatcher {
+            known: ProcScanWatcher::scan()?,
+            needed: true,
+        })
+    }
+
+    fn scan() -> io::Result<HashMap<u32, Identity>> {
+        Ok(fs::read_dir("/proc")?
+            .filter_map(Result::ok)
+            .filter_map(|entry| entry.file_name().to_str().and_then(|name| name.parse().ok()))
+            .filter_map(|id| ProcScanWatcher::identity(id).map(|identity| (id, identity)))
+            .collect())
+    }
+
+    /// Returns the identity of the process `id`, or `None` if it has exited.
+    fn identity(id: u32) -> Option<Identity> {
+        let stat = fs::read_to_string(format!("/proc/{}/stat", id)).ok()?;
+        let (open, close) = (stat.find('(')?, stat.rfind(')')?);
+        let comm = stat.get(open + 1..close)?.to_owned();
+        // The start time is field 22 in proc(5), the 20th after the command name.
+        let start_time = stat[close + 1..].split_whitespace().nth(19)?.parse().ok()?;
+        let exe = fs::read_link(format!("/proc/{}/exe", id)).ok();
+        Some((start_time, comm, exe))
+    }
+}
+
+impl ProcessWatcher for ProcScanWatcher {
+    fn wait(&mut self, timeout: Duration) -> HcbResult<Vec<ProcessEvent>> {
+        thread::sleep(timeout);
+        if !self.needed {
+            return Ok(Vec::new());
+        }
+        let current = ProcScanWatcher::scan()?;
+        Ok(diff_identities(&mut self.known, current))
+    }
+
+    fn set_needed(&mut self, needed: bool) {
+        self.needed = needed;
+    }
+}
+
+/// Returns the process connector watcher, or the `/proc` scanning watcher if the process
+/// connector cannot be subscribed to.
+pub fn watcher() -> io::Result<Box<dyn ProcessWatcher>> {
+    match ConnectorWatcher::new() {
+        Ok(watcher) => Ok(Box::new(watcher)),
+        Err(e) => {
+            info!(
+                "Process connector unavailable ({}), scanning /proc for new processes instead.",
+                e
+            );
+            Ok(Box::new(ProcScanWatcher::new()?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn message(what: u32, pid: u32, tgid: u32) -> Vec<u8> {
+        let mut buf = vec![0u8; NLMSG_HDRLEN + CN_MSG_LEN + PROC_EVENT_DATA + 24];
+        let len = buf.len() as u32;
+        buf[..4].copy_from_slice(&len.to_ne_bytes());
+        let event = NLMSG_HDRLEN + CN_MSG_LEN;
+        buf[event..event + 4].copy_from_slice(&what.to_ne_bytes());
+        let data = event + PROC_EVENT_DATA;
+        buf[data..data + 4].copy_from_slice(&pid.to_ne_bytes());
+        buf[data + 4..data + 8].copy_from_slice(&tgid.to_ne_bytes());
+        buf
+    }
+
+    #[test]
+    fn parses_connector_events() {
+        let mut buf = message(PROC_EVENT_EXEC, 12, 12);
+        buf.extend(message(PROC_EVENT_EXIT, 13, 12));
+        buf.extend(message(PROC_EVENT_EXIT, 14, 14));
+        buf.extend(message(0x1, 15, 15));
+        assert_eq!(
+            parse_events(&buf),
+            vec![ProcessEvent::Started(12), ProcessEvent::Exited(14)]
+        );
+        assert_eq!(parse_events(&buf[..10]), vec![]);
+    }
+
+    #[test]
+    fn scans_proc() {
+        let mut watcher = ProcScanWatcher::new().unwrap();
+        let (_, ref comm, ref exe) = watcher.known[&process::id()];
+        assert!(!comm.is_empty());
+        assert_eq!(exe.as_ref(), ::std::env::current_exe().ok().as_ref());
+        let events = watcher.wait(Duration::from_millis(1)).unwrap();
+        assert!(!events.contains(&ProcessEvent::Exited(process::id())));
+    }
+}
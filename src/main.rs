@@ -1,6 +1,5 @@
-//#[macro_use]
+extern crate ctrlc;
 extern crate failure;
-#[macro_use]
 extern crate structopt;
 extern crate rlhcbfix;
 #[macro_use]
@@ -8,13 +7,14 @@ extern crate log;
 extern crate pretty_env_logger;
 
 use std::env;
-use std::thread;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::process;
 
 use failure::Error;
 use structopt::StructOpt;
 
-use rlhcbfix::manage_rl_threads;
+use rlhcbfix::{config, launch, manage_reloading, Backend, Config, Family, FileWatcher, Launch,
+               Mode, Policy, ProcessWatcher, Selector, Settings, Shutdown, SystemClock};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "rlhcbfix")]
@@ -22,45 +22,320 @@ struct Opt {
     /// Verbose mode
     #[structopt(short = "v", long = "verbose")]
     verbose: bool,
-    /// Polling interval (in seconds)
-    #[structopt(short = "p", long = "poll", default_value = "1")]
-    poll_interval: u64,
-    /// Settling period (in seconds)
-    #[structopt(short = "s", long = "settle", default_value = "15")]
-    settling_period: u64,
+    /// Configuration file, which is reloaded whenever it changes (default: rlhcbfix/config.toml
+    /// in the user's config directory)
+    #[structopt(long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// The process to manage: [name:|path:|cmdline:][exact:|glob:|regex:]pattern, pid:id or,
+    /// on Linux, steam:appid[:pattern] (default: RocketLeague.exe)
+    #[structopt(short = "t", long = "target")]
+    target: Option<Selector>,
+    /// Polling interval in seconds (default: 1)
+    #[structopt(short = "p", long = "poll")]
+    poll_interval: Option<u64>,
+    /// Settling period in seconds (default: 15)
+    #[structopt(short = "s", long = "settle")]
+    settling_period: Option<u64>,
+    /// Number of most active threads to assign to separate cores (default: 3)
+    #[structopt(short = "n", long = "threads")]
+    hot_threads: Option<usize>,
+    /// Weight of the newest poll when averaging thread activity, from 0 (exclusive) to 1
+    /// (default: 1)
+    #[structopt(long = "smoothing")]
+    smoothing: Option<f64>,
+    /// How much busier a thread must be than a current hot thread to replace it, e.g. 0.2
+    /// (default: 0)
+    #[structopt(long = "hysteresis")]
+    hysteresis: Option<f64>,
+    /// CPUs the hot threads may be assigned to, such as 0-7 (default: all)
+    #[structopt(long = "cores")]
+    cores: Option<String>,
+    /// How to choose cores: physical, spread, least-loaded, or a list of CPUs such as 1,3,5
+    /// (default: physical)
+    #[structopt(long = "policy")]
+    policy: Option<Policy>,
+    /// How to hold threads on their cores: soft (ideal processor, Windows only), hard (pin to
+    /// the CPU) or hard-core (pin to the CPU and its SMT siblings) (default: soft on Windows,
+    /// hard on Linux)
+    #[structopt(long = "mode")]
+    mode: Option<Mode>,
+    /// Keep the game's other threads off the CPUs reserved for the hot threads
+    #[structopt(long = "evict")]
+    evict: bool,
+    /// Keep the user's other processes off the CPUs reserved for the hot threads while the game
+    /// runs
+    #[structopt(long = "reserve-system")]
+    reserve_system: bool,
+    /// Hold threads on particular CPUs, such as "name:RenderThread* -> 4", "rank:4 -> physical",
+    /// "share<1% -> efficiency" or "name:Audio* -> physical:l3=0". May be given several times;
+    /// each thread follows the first rule it matches
+    #[structopt(long = "rule")]
+    rules: Vec<String>,
+    /// Also monitor the threads of the process's descendants, such as helper processes
+    #[structopt(long = "descendants")]
+    descendants: bool,
+    /// Log the placement changes that would be made instead of making them
+    #[structopt(long = "dry-run")]
+    dry_run: bool,
+    /// Also manage another process, overriding the options above with key=value pairs separated
+    /// by semicolons, e.g. "name=encoder;target=obs64.exe;threads=2;cores=8-11"
+    #[structopt(long = "profile")]
+    profiles: Vec<String>,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    /// Run a command, such as a game's launch command, and manage the target once it starts it.
+    /// Exits with the command's exit code once the target or the command exits
+    #[structopt(name = "run")]
+    Run {
+        /// The command to run followed by its arguments, e.g. -- %command% in Steam
+        #[structopt(raw(required = "true"))]
+        command: Vec<String>,
+    },
+}
+
+impl Opt {
+    /// Returns the settings given on the command line, which override those of the
+    /// configuration's only profile and underlie those given with `--profile`.
+    fn overrides(&self) -> Vec<(&'static str, String)> {
+        let mut overrides = Vec::new();
+        {
+            let mut set = |key, value: Option<String>| {
+                if let Some(value) = value {
+                    overrides.push((key, value));
+                }
+            };
+            set("target", self.target.as_ref().map(Selector::to_string));
+            set("poll", self.poll_interval.map(|poll| poll.to_string()));
+            set("settle", self.settling_period.map(|settle| settle.to_string()));
+            set("threads", self.hot_threads.map(|threads| threads.to_string()));
+            set("smoothing", self.smoothing.map(|smoothing| smoothing.to_string()));
+            set("hysteresis", self.hysteresis.map(|hysteresis| hysteresis.to_string()));
+            set("cores", self.cores.clone());
+            set("policy", self.policy.as_ref().map(Policy::to_string));
+            set("mode", self.mode.map(|mode| mode.to_string()));
+            for &(key, enabled) in &[
+                ("evict", self.evict),
+                ("reserve-system", self.reserve_system),
+                ("descendants", self.descendants),
+            ] {
+                set(key, if enabled { Some("true".to_owned()) } else { None });
+            }
+            for rule in &self.rules {
+                set("rule", Some(rule.clone()));
+            }
+        }
+        overrides
+    }
+}
+
+/// Returns the profiles of the configuration, with the command line applied.
+///
+/// Settings given on the command line would apply to every profile alike, so they are refused
+/// when the configuration has more than one. Each `--profile` starts from the defaults with the
+/// command line's settings applied.
+fn profiles(opt: &Opt, config: Config) -> Result<Vec<Settings>, Error> {
+    let overrides = opt.overrides();
+    let apply = |profile: &mut Settings| {
+        overrides
+            .iter()
+            .try_for_each(|&(key, ref value)| profile.set(key, value))
+    };
+    let mut profiles = config.profiles;
+    if !overrides.is_empty() {
+        if profiles.len() > 1 {
+            let names: Vec<&str> = profiles.iter().map(|p| p.name.as_str()).collect();
+            return Err(failure::err_msg(format!(
+                "The configuration has several profiles ({}), so --{} cannot apply to them; set it in each profile instead",
+                names.join(", "),
+                overrides[0].0
+            )));
+        }
+        for profile in &mut profiles {
+            apply(profile)?;
+        }
+    }
+    let mut base = Settings::default();
+    apply(&mut base)?;
+    for (index, spec) in opt.profiles.iter().enumerate() {
+        let mut profile = Settings {
+            name: format!("profile{}", index + 1),
+            ..base.clone()
+        };
+        profile.apply_profile(spec)?;
+        profiles.push(profile);
+    }
+    Ok(profiles)
 }
 
-fn run() -> Result<(), Error> {
+#[cfg(target_os = "linux")]
+fn watch_config(path: &Path) -> Result<Box<dyn FileWatcher>, Error> {
+    Ok(rlhcbfix::linux::reload::watcher(path)?)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn watch_config(path: &Path) -> Result<Box<dyn FileWatcher>, Error> {
+    Ok(Box::new(rlhcbfix::ModifiedWatcher::new(path)?))
+}
+
+fn run() -> Result<i32, Error> {
     let opt: Opt = Opt::from_args();
+    let config = match opt.config {
+        Some(ref path) => Config::load(path)?,
+        None => Config::load_default()?,
+    };
     if opt.verbose {
         env::set_var("RLHCB_LOG", "rlhcbfix=debug");
         pretty_env_logger::try_init_custom_env("RLHCB_LOG")?;
+    } else if let Some(ref level) = config.log {
+        env::set_var("RLHCB_LOG", format!("rlhcbfix={}", level));
+        pretty_env_logger::try_init_custom_env("RLHCB_LOG")?;
     } else {
         pretty_env_logger::try_init()?;
     }
-    let poll_interval = Duration::from_secs(opt.poll_interval);
-    let settling_period = Duration::from_secs(opt.settling_period);
-    let retry_period = Duration::from_secs(5);
-
-    loop {
-        match manage_rl_threads(poll_interval, settling_period) {
-            Err(e) => match e {
-                rlhcbfix::Error::NoProcess => {}
-                rlhcbfix::Error::Windows(ref we) if we.code() == 31 => {}
-                e @ _ => {
-                    Err(e)?;
-                }
-            },
-            Ok(_) => {}
+    let profiles = profiles(&opt, config)?;
+    let mut watched = match opt.config.clone().or_else(config::default_path) {
+        Some(path) => match watch_config(&path) {
+            Ok(watcher) => Some((path, watcher)),
+            Err(e) => {
+                warn!("Not watching {} for changes: {}", path.display(), e);
+                None
+            }
+        },
+        None => None,
+    };
+    // The logging level is only read at startup.
+    let mut reload = || {
+        let (path, watcher) = watched.as_mut()?;
+        match watcher.changed() {
+            Ok(true) => {}
+            Ok(false) => return None,
+            Err(e) => {
+                warn!("Could not check {} for changes: {}", path.display(), e);
+                return None;
+            }
+        }
+        match Config::load(path)
+            .map_err(Error::from)
+            .and_then(|config| self::profiles(&opt, config))
+        {
+            Ok(profiles) => {
+                info!("Reloaded {}.", path.display());
+                Some(profiles)
+            }
+            Err(e) => {
+                warn!("Keeping the current configuration: {}", e);
+                None
+            }
+        }
+    };
+    let shutdown = Shutdown::new();
+    match opt.command {
+        Some(Command::Run { ref command }) => {
+            #[cfg(target_os = "linux")]
+            let signals = launch::Signals::block()?;
+            let launched = Launch::spawn(command)?;
+            let id = launched.id();
+            info!("Started process {}.", id);
+            // Placement is restored at once, while the command decides when to exit. On Windows a
+            // child sharing the console receives Ctrl+C and Ctrl+Break itself.
+            let handler = shutdown.clone();
+            #[cfg(target_os = "linux")]
+            signals.forward(id, move || handler.request())?;
+            #[cfg(not(target_os = "linux"))]
+            ctrlc::set_handler(move || handler.request())?;
+            let family = Some(launched.family());
+            if let Err(e) = run_native(&profiles, &shutdown, opt.dry_run, family, &mut reload) {
+                error!("{}", e);
+            }
+            Ok(launch::exit_code(launched.wait()?))
         }
-        warn!(
-            "No Rocket League process found. Retrying in {} seconds.",
-            retry_period.as_secs()
-        );
-        thread::sleep(retry_period);
+        None => {
+            let handler = shutdown.clone();
+            ctrlc::set_handler(move || handler.request())?;
+            run_native(&profiles, &shutdown, opt.dry_run, None, &mut reload)?;
+            Ok(0)
+        }
+    }
+}
+
+/// Returns the changed profiles, if the configuration has changed.
+type Reload<'a> = dyn FnMut() -> Option<Vec<Settings>> + 'a;
+
+#[cfg(any(windows, target_os = "linux"))]
+fn run_native(
+    profiles: &[Settings],
+    shutdown: &Shutdown,
+    dry_run: bool,
+    family: Option<Family>,
+    reload: &mut Reload,
+) -> Result<(), Error> {
+    if dry_run {
+        info!("Dry run: no thread or process will be changed.");
+        let backend = rlhcbfix::DryRun::new(rlhcbfix::NativeBackend);
+        run_with_backend(&backend, profiles, shutdown, family, reload)
+    } else {
+        run_with_backend(&rlhcbfix::NativeBackend, profiles, shutdown, family, reload)
     }
 }
 
+#[cfg(not(any(windows, target_os = "linux")))]
+fn run_native(
+    _profiles: &[Settings],
+    _shutdown: &Shutdown,
+    _dry_run: bool,
+    _family: Option<Family>,
+    _reload: &mut Reload,
+) -> Result<(), Error> {
+    Err(failure::err_msg("No process backend is available for this platform."))
+}
+
+#[cfg(target_os = "linux")]
+fn run_with_backend<B: Backend>(
+    backend: &B,
+    profiles: &[Settings],
+    shutdown: &Shutdown,
+    family: Option<Family>,
+    reload: &mut Reload,
+) -> Result<(), Error> {
+    let mut watcher = rlhcbfix::linux::watch::watcher()?;
+    run_with_watcher(backend, &mut watcher, profiles, shutdown, family, reload)
+}
+
+#[cfg(windows)]
+fn run_with_backend<B: Backend>(
+    backend: &B,
+    profiles: &[Settings],
+    shutdown: &Shutdown,
+    family: Option<Family>,
+    reload: &mut Reload,
+) -> Result<(), Error> {
+    let mut watcher = rlhcbfix::PollingWatcher::new(backend, &SystemClock)?;
+    run_with_watcher(backend, &mut watcher, profiles, shutdown, family, reload)
+}
+
+#[cfg_attr(not(any(windows, target_os = "linux")), allow(dead_code))]
+fn run_with_watcher<B: Backend, W: ProcessWatcher>(
+    backend: &B,
+    watcher: &mut W,
+    profiles: &[Settings],
+    shutdown: &Shutdown,
+    family: Option<Family>,
+    reload: &mut Reload,
+) -> Result<(), Error> {
+    manage_reloading(backend, &SystemClock, watcher, profiles, shutdown, family, reload)?;
+    Ok(())
+}
+
 fn main() {
-    run().unwrap()
+    match run() {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1)
+        }
+    }
 }
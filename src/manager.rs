@@ -0,0 +1,573 @@
+//! Managing the hot threads of one process, one poll at a time.
+
+use std::io;
+use std::time::Instant;
+
+use allocator::CoreAllocator;
+use backend::{Backend, OsProcess};
+use evict::Evictor;
+use governor::{Decision, Governor, Snapshot};
+use launch::Family;
+use placement::Placement;
+use policy::{AssignmentPolicy, LoadMeter, PolicyContext};
+use procext::MonitoredProcess;
+use reserve::Reservation;
+use rules::RuleEngine;
+use settings::Settings;
+use topology::Topology;
+use watch::ProcessEvent;
+use {Error, HcbResult};
+
+/// Returns true if every hot thread is held where `placement` put it.
+fn hot_in_place<P: OsProcess>(
+    ids: &[u32],
+    placement: &Placement,
+    process: &MonitoredProcess<P>,
+) -> HcbResult<bool> {
+    for (index, id) in ids.iter().enumerate() {
+        if !placement.holds(index, process.threads()[id].thread())? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Returns true if the error means a thread exited while the process itself still runs.
+fn thread_gone<P: OsProcess>(err: &Error, process: &P) -> bool {
+    let not_found = match *err {
+        Error::Io(ref ie) => ie.kind() == io::ErrorKind::NotFound,
+        #[cfg(windows)]
+        Error::Windows(ref we) => we.code() == 31,
+        _ => false,
+    };
+    not_found && process.running()
+}
+
+fn place_hot<P: OsProcess>(
+    ids: &[u32],
+    placement: &Placement,
+    process: &mut MonitoredProcess<P>,
+) -> HcbResult<()> {
+    debug!(
+        "Placing {:?} on CPUs {:?} in {} mode",
+        ids,
+        placement.cpus(),
+        placement.mode()
+    );
+    for (index, id) in ids.iter().enumerate() {
+        let thread = process.threads_mut().get_mut(id).unwrap();
+        placement.apply(index, thread)?;
+    }
+    Ok(())
+}
+
+/// Returns the backend's topology, or assumes two adjacently numbered SMT siblings per core for
+/// every CPU up to the highest one the process may use.
+fn discover_topology<B: Backend>(backend: &B, allowed: usize) -> Topology {
+    match backend.topology() {
+        Ok(Some(topology)) => return topology,
+        Ok(None) => {}
+        Err(e) => warn!("Could not discover the CPU topology: {}", e),
+    }
+    Topology::uniform(usize::BITS - allowed.leading_zeros(), 2)
+}
+
+/// The state of one managed process under one profile.
+///
+/// The caller drives it with [`step`](#method.step) once per poll and must call
+/// [`restore`](#method.restore) when it stops managing the process.
+#[derive(Debug)]
+pub struct Manager<B: Backend> {
+    settings: Settings,
+    process: MonitoredProcess<B::Process>,
+    /// The CPUs of the profile's pool which the process may run on.
+    allowed: usize,
+    topology: Topology,
+    load: LoadMeter,
+    governor: Governor,
+    /// Where the assigned threads were put, in the same order as the threads.
+    placement: Option<Placement>,
+    /// The threads which were placed there.
+    placed: Vec<u32>,
+    evictor: Evictor,
+    rules: RuleEngine,
+    reservation: Reservation<B::Process>,
+    /// Processes the reservation must not move besides the process and its descendants.
+    exempt: Vec<u32>,
+    /// The process and its descendants, once they have been looked for. New descendants are
+    /// then found as the watcher reports them starting.
+    family: Option<Family>,
+}
+
+/// What a profile's settings make of a process, worked out before any thread is changed.
+struct Prepared {
+    allowed: usize,
+    topology: Topology,
+    evictor: Evictor,
+}
+
+/// Checks that a profile can be satisfied for a process beside the CPUs `claimed` by other
+/// profiles, failing early if it never can be.
+fn prepare<B: Backend>(
+    backend: &B,
+    process: &MonitoredProcess<B::Process>,
+    settings: &Settings,
+    claimed: usize,
+) -> HcbResult<Prepared> {
+    prepare_within(backend, process.process().affinity_mask()?, settings, claimed)
+}
+
+/// Checks that a profile can be satisfied for a process with the affinity mask `affinity`,
+/// beside the CPUs `claimed` by other profiles.
+fn prepare_within<B: Backend>(
+    backend: &B,
+    affinity: usize,
+    settings: &Settings,
+    claimed: usize,
+) -> HcbResult<Prepared> {
+    let allowed = affinity & settings.cores;
+    if allowed == 0 {
+        return Err(Error::Config(format!(
+            "Profile '{}' has no CPUs the process may run on",
+            settings.name
+        )));
+    }
+    let available = allowed.count_ones();
+    if settings.hot_threads == 0 || settings.hot_threads > available as usize {
+        return Err(Error::HotThreads {
+            requested: settings.hot_threads,
+            available,
+        });
+    }
+    let topology = discover_topology(backend, allowed);
+    debug!(
+        "Found {} logical CPUs in {} physical cores.",
+        topology.cpus().len(),
+        topology.cores().len()
+    );
+    let evictor = Evictor::new(allowed);
+    let context = PolicyContext {
+        topology: &topology,
+        allowed,
+        load: None,
+    };
+    let hot = plan(settings, &context, &evictor, settings.hot_threads)?.reserved();
+    RuleEngine::check(
+        &settings.rules,
+        &topology,
+        allowed,
+        settings.hot_threads,
+        hot,
+        claimed,
+    )?;
+    Ok(Prepared {
+        allowed,
+        topology,
+        evictor,
+    })
+}
+
+/// Fails if a profile which has no process yet could never be satisfied on this machine.
+pub fn check_waiting<B: Backend>(backend: &B, settings: &Settings) -> HcbResult<()> {
+    let affinity = match backend.topology() {
+        Ok(Some(topology)) => topology
+            .cpus()
+            .iter()
+            .filter(|cpu| cpu.id < usize::BITS)
+            .fold(0, |mask, cpu| mask | 1 << cpu.id),
+        _ => !0,
+    };
+    prepare_within(backend, affinity, settings, 0).map(|_| ())
+}
+
+/// Places `count` hot threads as `settings` say within `context`, checking that the process's
+/// other threads would still have CPUs to run on.
+fn plan(
+    settings: &Settings,
+    context: &PolicyContext,
+    evictor: &Evictor,
+    count: usize,
+) -> HcbResult<Placement> {
+    let cpus = settings.policy.assign(count, context)?;
+    let placement = Placement::new(settings.mode, cpus, context.topology, context.allowed)?;
+    if settings.evict {
+        evictor.clone().reserve(placement.reserved())?;
+    }
+    Ok(placement)
+}
+
+/// Returns true if changing from `old` to `new` changes where or how the hot threads are held,
+/// rather than only when they are picked.
+fn placement_changed(old: &Settings, new: &Settings) -> bool {
+    old.hot_threads != new.hot_threads
+        || old.cores != new.cores
+        || old.policy != new.policy
+        || old.mode != new.mode
+        || old.evict != new.evict
+        || old.reserve_system != new.reserve_system
+        || old.descendants != new.descendants
+        || old.rules != new.rules
+}
+
+impl<B: Backend> Manager<B> {
+    /// Prepares to manage a process, failing early if the profile can never be satisfied.
+    pub fn new(
+        backend: &B,
+        mut process: MonitoredProcess<B::Process>,
+        settings: &Settings,
+    ) -> HcbResult<Manager<B>> {
+        let prepared = prepare(backend, &process, settings, 0)?;
+        process.set_smoothing(settings.smoothing);
+        let reservation = Reservation::new(process.process())?;
+        Ok(Manager {
+            settings: settings.clone(),
+            process,
+            allowed: prepared.allowed,
+            topology: prepared.topology,
+            load: LoadMeter::new(),
+            governor: Governor::new(settings.settling_period),
+            placement: None,
+            placed: Vec::new(),
+            evictor: prepared.evictor,
+            rules: RuleEngine::new(&settings.rules),
+            reservation,
+            exempt: Vec::new(),
+            family: None,
+        })
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn process(&self) -> &MonitoredProcess<B::Process> {
+        &self.process
+    }
+
+    /// Fails if `settings` could never be satisfied for the managed process, or if switching to
+    /// them would place the hot threads again straight away and the CPUs they need are claimed
+    /// by other profiles in `allocator`.
+    pub fn check(
+        &self,
+        backend: &B,
+        settings: &Settings,
+        allocator: &CoreAllocator,
+    ) -> HcbResult<()> {
+        if placement_changed(&self.settings, settings) {
+            self.prepare_switch(backend, settings, allocator)?;
+        }
+        Ok(())
+    }
+
+    /// Works out what switching to `settings` needs and which hot threads would be placed
+    /// again straight away, without changing anything.
+    fn prepare_switch(
+        &self,
+        backend: &B,
+        settings: &Settings,
+        allocator: &CoreAllocator,
+    ) -> HcbResult<(Prepared, Option<Vec<u32>>)> {
+        let prepared = prepare(
+            backend,
+            &self.process,
+            settings,
+            !allocator.available(&settings.name),
+        )?;
+        let settled = match self.governor.assigned() {
+            Some(hot)
+                if self.governor.stable()
+                    && hot.len() == settings.hot_threads
+                    && hot.iter().all(|id| self.process.threads().contains_key(id)) =>
+            {
+                Some(hot.to_vec())
+            }
+            _ => None,
+        };
+        if let Some(ref hot) = settled {
+            let context = PolicyContext {
+                topology: &prepared.topology,
+                allowed: prepared.allowed & allocator.available(&settings.name),
+                load: self.load.load(),
+            };
+            plan(settings, &context, &prepared.evictor, hot.len())?;
+        }
+        Ok((prepared, settled))
+    }
+
+    /// Switches to changed settings for the same profile, keeping what has been learnt about
+    /// the process's threads.
+    ///
+    /// Changes to the timing or smoothing take effect from the next step. Changes to where or
+    /// how the hot threads are held give every thread its original placement back, then place
+    /// the hot threads again straight away if they had settled, rather than waiting for them to
+    /// settle again. Fails without changing anything if the settings can never be satisfied, or
+    /// if the hot threads cannot be placed again beside the other profiles' CPUs. If placing
+    /// them fails anyway, they are left unassigned until they settle again.
+    pub fn reconfigure(
+        &mut self,
+        backend: &B,
+        settings: &Settings,
+        allocator: &mut CoreAllocator,
+    ) -> HcbResult<()> {
+        if !placement_changed(&self.settings, settings) {
+            self.process.set_smoothing(settings.smoothing);
+            self.governor.set_settling_period(settings.settling_period);
+            self.settings = settings.clone();
+            return Ok(());
+        }
+        let (prepared, settled) = self.prepare_switch(backend, settings, allocator)?;
+        self.process.set_smoothing(settings.smoothing);
+        self.governor.set_settling_period(settings.settling_period);
+        self.restore(allocator);
+        self.settings = settings.clone();
+        self.allowed = prepared.allowed;
+        self.topology = prepared.topology;
+        self.evictor = prepared.evictor;
+        self.rules = RuleEngine::new(&settings.rules);
+        self.process.exclude(&[]);
+        self.placement = None;
+        self.placed.clear();
+        if !settings.descendants {
+            self.process.set_descendants(Vec::new());
+        }
+        self.family = None;
+        self.update_exemptions();
+        match settled {
+            Some(hot) => {
+                info!("Reassigning thread affinities for the new settings.");
+                if let Err(e) = self.assign(backend, hot, allocator) {
+                    warn!(
+                        "Leaving the hot threads of profile '{}' unassigned: {}",
+                        settings.name, e
+                    );
+                    self.governor = Governor::new(settings.settling_period);
+                }
+            }
+            None => self.governor = Governor::new(settings.settling_period),
+        }
+        Ok(())
+    }
+
+    /// Keeps the system-wide reservation from moving the processes `ids`.
+    pub fn exempt(&mut self, ids: &[u32]) {
+        self.exempt = ids.to_vec();
+        self.update_exemptions();
+    }
+
+    fn update_exemptions(&mut self) {
+        let mut ids = self.process.process_ids();
+        ids.extend(&self.exempt);
+        self.reservation.exempt(&ids);
+    }
+
+    /// Looks through every process for descendants of the process if tracking them and they
+    /// have not been looked for yet.
+    fn find_descendants(&mut self, backend: &B) -> HcbResult<()> {
+        if !self.settings.descendants || self.family.is_some() {
+            return Ok(());
+        }
+        let mut family = Family::of(self.process.process())?;
+        for process in backend.processes()? {
+            self.add_descendant(backend, &mut family, process);
+        }
+        self.family = Some(family);
+        Ok(())
+    }
+
+    /// Monitors `process` if it is a descendant of the process not monitored yet.
+    fn add_descendant(&mut self, backend: &B, family: &mut Family, process: B::Process) {
+        let id = process.id();
+        if id == family.root() || !family.adopt(backend, &process) {
+            return;
+        }
+        if self.process.add_descendant(process) {
+            debug!("Monitoring descendant process {}.", id);
+            self.update_exemptions();
+        }
+    }
+
+    /// Samples the process at `now` and acts on whatever the governor decides.
+    ///
+    /// A thread which exits while it is being checked or placed is dropped at the next poll,
+    /// when the hot threads are ranked again without it.
+    pub fn step(
+        &mut self,
+        backend: &B,
+        now: Instant,
+        allocator: &mut CoreAllocator,
+    ) -> HcbResult<()> {
+        match self.try_step(backend, now, allocator) {
+            Err(ref e) if thread_gone(e, self.process.process()) => {
+                debug!("A thread exited while being placed: {}", e);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn try_step(
+        &mut self,
+        backend: &B,
+        now: Instant,
+        allocator: &mut CoreAllocator,
+    ) -> HcbResult<()> {
+        self.find_descendants(backend)?;
+        self.process.update_at(now)?;
+        if self.settings.policy.uses_load() {
+            self.load.sample(backend.cpu_times()?);
+        }
+        self.apply_rules(allocator)?;
+        let mut hot = match self.process.hot_threads(self.settings.hot_threads) {
+            Some(hot) => hot,
+            None => return Ok(()),
+        };
+        hot.sort_unstable();
+        let in_place = match self.placement {
+            Some(ref placement) => hot_in_place(&hot, placement, &self.process)?,
+            None => false,
+        };
+        let snapshot = Snapshot { hot, in_place };
+        for decision in self.governor.step(&snapshot, now) {
+            match decision {
+                Decision::Wait => {}
+                Decision::Changed(hot) => debug!("Hot threads changed: {:?}", hot),
+                Decision::Returned(hot) => {
+                    debug!("Previously set hot threads returned: {:?}", hot)
+                }
+                Decision::AnnounceSettling { remaining } => info!(
+                    "Threads appear to have settled. Assigning affinities on the next poll if stable after {} seconds.",
+                    remaining.as_secs()
+                ),
+                Decision::Assign(hot) => {
+                    info!("Assigning thread affinities.");
+                    match self.assign(backend, hot, allocator) {
+                        Ok(()) => {}
+                        Err(Error::Policy(e)) => {
+                            // Most likely another profile holds the CPUs, so try again once the
+                            // threads settle again.
+                            warn!(
+                                "Leaving the hot threads of profile '{}' unassigned: {}",
+                                self.settings.name, e
+                            );
+                            self.governor = Governor::new(self.settings.settling_period);
+                            break;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+                Decision::Correct(hot) => {
+                    info!("Correcting affinities.");
+                    if let Some(ref placement) = self.placement {
+                        place_hot(&hot, placement, &mut self.process)?;
+                    }
+                }
+            }
+        }
+        if self.settings.evict {
+            if let Some(assigned) = self.governor.assigned() {
+                let mut kept = assigned.to_vec();
+                kept.extend(self.rules.held());
+                self.evictor.update(&mut self.process, &kept);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if the manager acts on process events, so the watcher must keep finding
+    /// them even while every profile is managing its target.
+    pub fn watches_processes(&self) -> bool {
+        self.settings.reserve_system || self.settings.descendants
+    }
+
+    /// Acts on processes starting and exiting, such as by monitoring new descendants and moving
+    /// new processes off the CPUs reserved system-wide.
+    pub fn observe(&mut self, backend: &B, events: &[ProcessEvent]) -> HcbResult<()> {
+        if let Some(mut family) = self.family.take() {
+            // If events were lost, every process is looked through again at the next step.
+            if !events.contains(&ProcessEvent::Overflow) {
+                for &event in events {
+                    if let ProcessEvent::Started(id) = event {
+                        if let Ok(process) = backend.process(id) {
+                            self.add_descendant(backend, &mut family, process);
+                        }
+                    }
+                }
+                self.family = Some(family);
+            }
+        }
+        if self.settings.reserve_system {
+            self.reservation.update(backend, events)?;
+        }
+        Ok(())
+    }
+
+    /// Holds the threads the profile's rules match on their CPUs, away from the hot threads
+    /// and the other profiles' CPUs, claims those CPUs alongside the hot threads', and keeps
+    /// the threads from being picked as hot threads.
+    fn apply_rules(&mut self, allocator: &mut CoreAllocator) -> HcbResult<()> {
+        let reserved = self.placement.as_ref().map_or(0, Placement::reserved);
+        let allowed = self.allowed & allocator.available(&self.settings.name);
+        let released = self.rules
+            .apply(&mut self.process, &self.topology, allowed, reserved, &self.placed);
+        for id in released {
+            self.evictor.forget(id);
+        }
+        self.process.exclude(&self.rules.held());
+        allocator.claim(&self.settings.name, reserved | self.rules.cpus())
+    }
+
+    fn assign(
+        &mut self,
+        backend: &B,
+        hot: Vec<u32>,
+        allocator: &mut CoreAllocator,
+    ) -> HcbResult<()> {
+        let context = PolicyContext {
+            topology: &self.topology,
+            allowed: self.allowed & allocator.available(&self.settings.name),
+            load: self.load.load(),
+        };
+        // Checked before anything is claimed or changed.
+        let assigned = plan(&self.settings, &context, &self.evictor, hot.len())?;
+        allocator.claim(
+            &self.settings.name,
+            assigned.reserved() | self.rules.cpus(),
+        )?;
+        for id in self.placed.iter().filter(|id| !hot.contains(id)) {
+            if let Some(thread) = self.process.threads_mut().get_mut(id) {
+                if let Err(e) = thread.restore() {
+                    debug!("Could not restore thread {}: {}", id, e);
+                }
+            }
+        }
+        if self.settings.evict {
+            self.evictor.reserve(assigned.reserved())?;
+            for id in &hot {
+                let thread = self.process.threads_mut().get_mut(id).unwrap();
+                self.evictor.release(thread)?;
+            }
+        }
+        let reserved = assigned.reserved();
+        // Recorded first, so threads placed before one which exits are restored later.
+        self.placement = Some(assigned);
+        self.placed = hot;
+        if let Some(ref placement) = self.placement {
+            place_hot(&self.placed, placement, &mut self.process)?;
+        }
+        if self.settings.reserve_system {
+            self.reservation.reserve(backend, reserved)?;
+        }
+        Ok(())
+    }
+
+    /// Gives every thread and process which was changed its original placement back, and
+    /// releases the profile's CPUs.
+    pub fn restore(&mut self, allocator: &mut CoreAllocator) {
+        if self.process.process().running() || !self.process.descendants().is_empty() {
+            info!("Restoring original thread placement.");
+            self.process.restore();
+        }
+        self.reservation.restore();
+        allocator.release(&self.settings.name);
+    }
+}
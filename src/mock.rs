@@ -0,0 +1,475 @@
+//! A scriptable in-memory backend for exercising the manager without touching the OS.
+//!
+//! Each mock process follows a script of frames. A process starts in its first frame, or with no
+//! threads if its script is empty, and each `tick` moves every process on to its next frame:
+//! threads listed in it exist and accumulate the given activity, threads missing from it have
+//! exited. Once the script runs out the process exits. Reading a process never changes it.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{self, ErrorKind};
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use backend::{Backend, OsProcess, OsThread};
+use clock::{Clock, ManualClock};
+use HcbResult;
+
+/// A change made to a mock thread's placement through the backend, named after the method which
+/// made it.
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    SetIdealProcessor { thread: u32, processor: u32 },
+    SetAffinityMask { thread: u32, mask: usize },
+    SetProcessAffinityMask { process: u32, mask: usize },
+}
+
+/// One poll's worth of scripted thread activity.
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    activity: Vec<(u32, u64)>,
+    perturbations: Vec<(u32, u32)>,
+    affinity_perturbations: Vec<(u32, usize)>,
+}
+
+impl Frame {
+    /// Creates a frame from `(thread id, activity)` pairs, where activity is the number of
+    /// counter units each thread used since the previous frame.
+    pub fn from_activity(activity: &[(u32, u64)]) -> Frame {
+        Frame {
+            activity: activity.to_vec(),
+            ..Frame::default()
+        }
+    }
+
+    /// Moves a thread's ideal processor behind the manager's back, without recording an event.
+    pub fn perturb(mut self, id: u32, processor: u32) -> Frame {
+        self.perturbations.push((id, processor));
+        self
+    }
+
+    /// Changes a thread's affinity mask behind the manager's back, without recording an event.
+    pub fn perturb_affinity(mut self, id: u32, mask: usize) -> Frame {
+        self.affinity_perturbations.push((id, mask));
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ThreadState {
+    name: Option<String>,
+    counter: u64,
+    ideal: u32,
+    affinity: usize,
+}
+
+#[derive(Debug)]
+struct ProcessState {
+    id: u32,
+    name: String,
+    path: String,
+    command_line: String,
+    parent: u32,
+    start_time: u64,
+    owner: Option<u32>,
+    environment: Vec<(String, String)>,
+    thread_names: Vec<(u32, String)>,
+    /// Threads which exit as soon as anything tries to change them.
+    exiting: Vec<u32>,
+    affinity: usize,
+    script: VecDeque<Frame>,
+    running: bool,
+    threads: BTreeMap<u32, ThreadState>,
+}
+
+impl ProcessState {
+    fn advance(&mut self) {
+        let frame = match self.script.pop_front() {
+            Some(frame) => frame,
+            None => {
+                self.running = false;
+                self.threads.clear();
+                return;
+            }
+        };
+        let affinity = self.affinity;
+        let mut threads = BTreeMap::new();
+        for (id, activity) in frame.activity {
+            let name = self.thread_names
+                .iter()
+                .find(|&&(thread, _)| thread == id)
+                .map(|(_, name)| name.clone());
+            let mut thread = self.threads.remove(&id).unwrap_or(ThreadState {
+                name,
+                counter: 0,
+                ideal: 0,
+                affinity,
+            });
+            thread.counter += activity;
+            threads.insert(id, thread);
+        }
+        for (id, processor) in frame.perturbations {
+            if let Some(thread) = threads.get_mut(&id) {
+                thread.ideal = processor;
+            }
+        }
+        for (id, mask) in frame.affinity_perturbations {
+            if let Some(thread) = threads.get_mut(&id) {
+                thread.affinity = mask;
+            }
+        }
+        self.threads = threads;
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    processes: Vec<ProcessState>,
+    events: Vec<Event>,
+}
+
+type SharedState = Rc<RefCell<State>>;
+
+fn gone() -> io::Error {
+    io::Error::new(ErrorKind::NotFound, "mock thread has exited")
+}
+
+/// A backend whose processes and threads are scripted by the caller.
+#[derive(Debug, Clone, Default)]
+pub struct MockBackend {
+    state: SharedState,
+}
+
+impl MockBackend {
+    pub fn new() -> MockBackend {
+        MockBackend::default()
+    }
+
+    /// Adds a process which plays through `script` and may run on the CPUs in `affinity`,
+    /// starting in its first frame.
+    ///
+    /// A process with an empty script runs without threads until the next tick.
+    pub fn add_process(&self, id: u32, name: &str, affinity: usize, script: Vec<Frame>) {
+        let mut process = ProcessState {
+            id,
+            name: name.to_owned(),
+            path: name.to_owned(),
+            command_line: name.to_owned(),
+            parent: 0,
+            start_time: 0,
+            owner: Some(1000),
+            environment: Vec::new(),
+            thread_names: Vec::new(),
+            exiting: Vec::new(),
+            affinity,
+            script: script.into(),
+            running: true,
+            threads: BTreeMap::new(),
+        };
+        if !process.script.is_empty() {
+            process.advance();
+        }
+        self.state.borrow_mut().processes.push(process);
+    }
+
+    /// Moves every running process on to the next frame of its script.
+    pub fn tick(&self) {
+        for process in self.state.borrow_mut().processes.iter_mut() {
+            if process.running {
+                process.advance();
+            }
+        }
+    }
+
+    /// Sets the executable path and command line of a process, which default to its name.
+    pub fn set_command_line(&self, id: u32, path: &str, command_line: &str) {
+        for process in self.state.borrow_mut().processes.iter_mut() {
+            if process.id == id {
+                process.path = path.to_owned();
+                process.command_line = command_line.to_owned();
+            }
+        }
+    }
+
+    /// Sets the parent of a process, which defaults to 0.
+    pub fn set_parent(&self, id: u32, parent: u32) {
+        for process in self.state.borrow_mut().processes.iter_mut() {
+            if process.id == id {
+                process.parent = parent;
+            }
+        }
+    }
+
+    /// Sets when a process started, which defaults to 0.
+    pub fn set_start_time(&self, id: u32, start_time: u64) {
+        for process in self.state.borrow_mut().processes.iter_mut() {
+            if process.id == id {
+                process.start_time = start_time;
+            }
+        }
+    }
+
+    /// Sets who a process belongs to, which defaults to user 1000.
+    pub fn set_owner(&self, id: u32, owner: Option<u32>) {
+        for process in self.state.borrow_mut().processes.iter_mut() {
+            if process.id == id {
+                process.owner = owner;
+            }
+        }
+    }
+
+    /// Sets an environment variable of a process.
+    #[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+    pub fn set_environment_variable(&self, id: u32, name: &str, value: &str) {
+        for process in self.state.borrow_mut().processes.iter_mut() {
+            if process.id == id {
+                process.environment.push((name.to_owned(), value.to_owned()));
+            }
+        }
+    }
+
+    /// Names a thread of a process, both now and whenever it starts again. Threads are unnamed
+    /// by default.
+    pub fn set_thread_name(&self, id: u32, thread: u32, name: &str) {
+        for process in self.state.borrow_mut().processes.iter_mut() {
+            if process.id == id {
+                process.thread_names.push((thread, name.to_owned()));
+                if let Some(state) = process.threads.get_mut(&thread) {
+                    state.name = Some(name.to_owned());
+                }
+            }
+        }
+    }
+
+    /// Makes a thread of a process exit the next time its placement is changed, before the
+    /// change is made.
+    pub fn exit_thread_when_changed(&self, id: u32, thread: u32) {
+        for process in self.state.borrow_mut().processes.iter_mut() {
+            if process.id == id {
+                process.exiting.push(thread);
+            }
+        }
+    }
+
+    /// Returns every placement change made through the backend, in order.
+    pub fn events(&self) -> Vec<Event> {
+        self.state.borrow().events.clone()
+    }
+}
+
+impl Backend for MockBackend {
+    type Process = MockProcess;
+
+    fn processes(&self) -> HcbResult<Vec<MockProcess>> {
+        Ok(self.state
+            .borrow()
+            .processes
+            .iter()
+            .enumerate()
+            .filter(|&(_, p)| p.running)
+            .map(|(index, _)| MockProcess {
+                state: self.state.clone(),
+                index,
+            })
+            .collect())
+    }
+}
+
+/// A manual clock which moves a mock backend's processes on to their next frame each time it
+/// sleeps, so that one frame passes per poll.
+#[derive(Debug)]
+pub struct MockClock {
+    clock: ManualClock,
+    backend: MockBackend,
+}
+
+impl MockClock {
+    pub fn new(backend: &MockBackend) -> MockClock {
+        MockClock {
+            clock: ManualClock::new(),
+            backend: backend.clone(),
+        }
+    }
+
+    /// Returns how far the clock has moved since it was created.
+    pub fn elapsed(&self) -> Duration {
+        self.clock.elapsed()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.clock.sleep(duration);
+        self.backend.tick();
+    }
+}
+
+#[derive(Debug)]
+pub struct MockProcess {
+    state: SharedState,
+    index: usize,
+}
+
+impl MockProcess {
+    fn with<R, F: FnOnce(&mut ProcessState) -> R>(&self, f: F) -> R {
+        f(&mut self.state.borrow_mut().processes[self.index])
+    }
+}
+
+impl OsProcess for MockProcess {
+    type Thread = MockThread;
+
+    fn id(&self) -> u32 {
+        self.with(|p| p.id)
+    }
+
+    fn running(&self) -> bool {
+        self.with(|p| p.running)
+    }
+
+    fn name(&self) -> HcbResult<String> {
+        Ok(self.with(|p| p.name.clone()))
+    }
+
+    fn path(&self) -> HcbResult<PathBuf> {
+        Ok(self.with(|p| PathBuf::from(&p.path)))
+    }
+
+    fn command_line(&self) -> HcbResult<String> {
+        Ok(self.with(|p| p.command_line.clone()))
+    }
+
+    fn parent_id(&self) -> HcbResult<u32> {
+        Ok(self.with(|p| p.parent))
+    }
+
+    fn start_time(&self) -> HcbResult<u64> {
+        Ok(self.with(|p| p.start_time))
+    }
+
+    fn owner(&self) -> HcbResult<Option<u32>> {
+        Ok(self.with(|p| p.owner))
+    }
+
+    fn environment_variable(&self, name: &str) -> HcbResult<Option<String>> {
+        Ok(self.with(|p| {
+            p.environment
+                .iter()
+                .find(|&(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        }))
+    }
+
+    fn affinity_mask(&self) -> HcbResult<usize> {
+        Ok(self.with(|p| p.affinity))
+    }
+
+    fn set_affinity_mask(&mut self, mask: usize) -> HcbResult<usize> {
+        let mut state = self.state.borrow_mut();
+        let State {
+            ref mut processes,
+            ref mut events,
+        } = *state;
+        let process = &mut processes[self.index];
+        events.push(Event::SetProcessAffinityMask {
+            process: process.id,
+            mask,
+        });
+        for thread in process.threads.values_mut() {
+            thread.affinity = mask;
+        }
+        Ok(::std::mem::replace(&mut process.affinity, mask))
+    }
+
+    fn thread_ids(&self) -> HcbResult<Vec<u32>> {
+        Ok(self.with(|p| p.threads.keys().cloned().collect()))
+    }
+
+    fn thread(&self, id: u32) -> HcbResult<MockThread> {
+        if !self.with(|p| p.threads.contains_key(&id)) {
+            return Err(gone().into());
+        }
+        Ok(MockThread {
+            state: self.state.clone(),
+            index: self.index,
+            id,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct MockThread {
+    state: SharedState,
+    index: usize,
+    id: u32,
+}
+
+impl MockThread {
+    fn with<R, F: FnOnce(&mut ThreadState, &mut Vec<Event>) -> R>(&self, f: F) -> HcbResult<R> {
+        let mut state = self.state.borrow_mut();
+        let State {
+            ref mut processes,
+            ref mut events,
+        } = *state;
+        match processes[self.index].threads.get_mut(&self.id) {
+            Some(thread) => Ok(f(thread, events)),
+            None => Err(gone().into()),
+        }
+    }
+
+    /// Like `with`, but first ends the thread if it exits when changed.
+    fn change<R, F: FnOnce(&mut ThreadState, &mut Vec<Event>) -> R>(&self, f: F) -> HcbResult<R> {
+        {
+            let process = &mut self.state.borrow_mut().processes[self.index];
+            if let Some(index) = process.exiting.iter().position(|&id| id == self.id) {
+                process.exiting.remove(index);
+                process.threads.remove(&self.id);
+            }
+        }
+        self.with(f)
+    }
+}
+
+impl OsThread for MockThread {
+    fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn name(&self) -> HcbResult<Option<String>> {
+        self.with(|t, _| t.name.clone())
+    }
+
+    fn cpu_counter(&self) -> HcbResult<u64> {
+        self.with(|t, _| t.counter)
+    }
+
+    fn ideal_processor(&self) -> HcbResult<u32> {
+        self.with(|t, _| t.ideal)
+    }
+
+    fn set_ideal_processor(&mut self, processor: u32) -> HcbResult<u32> {
+        let thread = self.id;
+        self.change(|t, events| {
+            events.push(Event::SetIdealProcessor { thread, processor });
+            ::std::mem::replace(&mut t.ideal, processor)
+        })
+    }
+
+    fn affinity_mask(&self) -> HcbResult<usize> {
+        self.with(|t, _| t.affinity)
+    }
+
+    fn set_affinity_mask(&mut self, mask: usize) -> HcbResult<usize> {
+        let thread = self.id;
+        self.change(|t, events| {
+            events.push(Event::SetAffinityMask { thread, mask });
+            ::std::mem::replace(&mut t.affinity, mask)
+        })
+    }
+}
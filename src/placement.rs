@@ -0,0 +1,197 @@
+//! Applying chosen CPUs to the hot threads.
+
+use std::fmt;
+use std::str::FromStr;
+
+use backend::OsThread;
+use topology::Topology;
+use {Error, HcbResult};
+
+/// How the hot threads are held on their CPUs.
+///
+/// Linux has no ideal processors, so soft placement is refused there and hard placement is the
+/// default instead.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Set each thread's ideal processor, leaving the scheduler free to move it.
+    Soft,
+    /// Restrict each thread's affinity to its chosen CPU.
+    Hard,
+    /// Restrict each thread's affinity to its chosen CPU and that CPU's SMT siblings.
+    HardCore,
+}
+
+impl Default for Mode {
+    fn default() -> Mode {
+        if cfg!(target_os = "linux") {
+            Mode::Hard
+        } else {
+            Mode::Soft
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> HcbResult<Mode> {
+        match s {
+            "soft" if cfg!(target_os = "linux") => Err(Error::Config(
+                "Linux has no ideal processors, so mode 'soft' is not available; use hard or \
+                 hard-core instead"
+                    .to_owned(),
+            )),
+            "soft" => Ok(Mode::Soft),
+            "hard" => Ok(Mode::Hard),
+            "hard-core" => Ok(Mode::HardCore),
+            _ => Err(Error::Config(format!("Unknown mode '{}'", s))),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Mode::Soft => write!(f, "soft"),
+            Mode::Hard => write!(f, "hard"),
+            Mode::HardCore => write!(f, "hard-core"),
+        }
+    }
+}
+
+/// The CPUs chosen for a set of hot threads, and how to hold the threads there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    mode: Mode,
+    cpus: Vec<u32>,
+    masks: Vec<usize>,
+}
+
+impl Placement {
+    /// Creates a placement for threads assigned to `cpus`, in order.
+    ///
+    /// Every affinity mask is checked against the process's affinity mask `allowed`, since a
+    /// thread can only run on the processors its process can run on.
+    pub fn new(
+        mode: Mode,
+        cpus: Vec<u32>,
+        topology: &Topology,
+        allowed: usize,
+    ) -> HcbResult<Placement> {
+        let masks = cpus
+            .iter()
+            .map(|&cpu| match mode {
+                Mode::HardCore => {
+                    topology
+                        .siblings(cpu)
+                        .into_iter()
+                        .chain(Some(cpu))
+                        .filter(|&cpu| cpu < usize::BITS)
+                        .fold(0, |mask, cpu| mask | 1 << cpu)
+                        & allowed
+                }
+                Mode::Soft | Mode::Hard => 1usize.checked_shl(cpu).unwrap_or(0),
+            })
+            .collect::<Vec<usize>>();
+        if let Some(mask) = masks
+            .iter()
+            .find(|&&mask| mask == 0 || mask & !allowed != 0)
+        {
+            return Err(Error::Policy(format!(
+                "CPU set {:#x} is not within the process affinity mask {:#x}",
+                mask, allowed
+            )));
+        }
+        Ok(Placement { mode, cpus, masks })
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Returns the CPU chosen for each thread.
+    pub fn cpus(&self) -> &[u32] {
+        &self.cpus
+    }
+
+    /// Returns the affinity mask for each thread in the hard modes.
+    pub fn masks(&self) -> &[usize] {
+        &self.masks
+    }
+
+    /// Returns the CPUs the placement holds threads on, as an affinity mask.
+    pub fn reserved(&self) -> usize {
+        self.masks.iter().fold(0, |reserved, mask| reserved | mask)
+    }
+
+    /// Moves the `index`th thread onto its CPU.
+    pub fn apply<T: OsThread>(&self, index: usize, thread: &mut T) -> HcbResult<()> {
+        match self.mode {
+            Mode::Soft => thread.set_ideal_processor(self.cpus[index]).map(|_| ()),
+            Mode::Hard | Mode::HardCore => thread.set_affinity_mask(self.masks[index]).map(|_| ()),
+        }
+    }
+
+    /// Returns true if the `index`th thread is still held on its CPU.
+    pub fn holds<T: OsThread>(&self, index: usize, thread: &T) -> HcbResult<bool> {
+        Ok(match self.mode {
+            Mode::Soft => thread.ideal_processor()? == self.cpus[index],
+            Mode::Hard | Mode::HardCore => thread.affinity_mask()? == self.masks[index],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use topology::Cpu;
+
+    #[test]
+    fn builds_masks_for_each_mode() {
+        let topology = Topology::uniform(8, 2);
+        let soft = Placement::new(Mode::Soft, vec![1, 4], &topology, 0xff).unwrap();
+        assert_eq!(soft.masks(), &[0b10, 0b1_0000]);
+        let core = Placement::new(Mode::HardCore, vec![1, 4], &topology, 0xff).unwrap();
+        assert_eq!(core.masks(), &[0b11, 0b11_0000]);
+        let core = Placement::new(Mode::HardCore, vec![1, 4], &topology, 0b1_1110).unwrap();
+        assert_eq!(core.masks(), &[0b10, 0b1_0000]);
+    }
+
+    #[test]
+    fn leaves_siblings_beyond_the_mask_out_of_hard_core_masks() {
+        let cpus = (0..2)
+            .flat_map(|core| {
+                vec![core, core + usize::BITS].into_iter().map(move |id| Cpu {
+                    id,
+                    core,
+                    package: 0,
+                    l2: core,
+                    l3: 0,
+                    node: 0,
+                    efficiency: false,
+                })
+            })
+            .collect();
+        let topology = Topology::new(cpus);
+        let core = Placement::new(Mode::HardCore, vec![1], &topology, 0b11).unwrap();
+        assert_eq!(core.masks(), &[0b10]);
+        assert!(Placement::new(Mode::Hard, vec![usize::BITS], &topology, !0).is_err());
+    }
+
+    #[test]
+    fn rejects_cpus_outside_the_process() {
+        let topology = Topology::uniform(8, 2);
+        assert!(Placement::new(Mode::Hard, vec![1, 4], &topology, 0b1111).is_err());
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!("hard-core".parse::<Mode>().unwrap(), Mode::HardCore);
+        assert_eq!(Mode::Hard.to_string(), "hard");
+        assert!("firm".parse::<Mode>().is_err());
+        // Linux has no ideal processors to hold threads on softly.
+        let linux = cfg!(target_os = "linux");
+        assert_eq!("soft".parse::<Mode>().is_ok(), !linux);
+        assert_eq!(Mode::default() == Mode::Hard, linux);
+    }
+}
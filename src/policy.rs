@@ -0,0 +1,378 @@
+//! Strategies for choosing which CPUs the hot threads are assigned to.
+
+use std::fmt::{self, Debug};
+use std::str::FromStr;
+
+use backend::CpuTime;
+use topology::Topology;
+use {Error, HcbResult};
+
+/// What a policy knows about the machine when it is asked for an assignment.
+#[derive(Debug, Clone)]
+pub struct PolicyContext<'a> {
+    pub topology: &'a Topology,
+    /// The CPUs the target process may run on.
+    pub allowed: usize,
+    /// The recent busy fraction of each CPU, indexed by CPU id, if the backend can measure it.
+    pub load: Option<&'a [f64]>,
+}
+
+impl<'a> PolicyContext<'a> {
+    fn is_allowed(&self, cpu: u32) -> bool {
+        cpu < usize::BITS && self.allowed & 1 << cpu != 0
+    }
+
+    /// Returns the allowed CPUs of each physical core, omitting cores with none allowed.
+    fn allowed_cores(&self) -> Vec<Vec<u32>> {
+        self.topology
+            .cores()
+            .into_iter()
+            .map(|siblings| {
+                siblings
+                    .into_iter()
+                    .filter(|&cpu| self.is_allowed(cpu))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|siblings| !siblings.is_empty())
+            .collect()
+    }
+}
+
+/// Turns successive samples of cumulative CPU time into recent per-CPU load.
+#[derive(Debug, Clone, Default)]
+pub struct LoadMeter {
+    prev: Vec<CpuTime>,
+    load: Vec<f64>,
+}
+
+impl LoadMeter {
+    pub fn new() -> LoadMeter {
+        LoadMeter::default()
+    }
+
+    /// Records a new sample, updating the load to cover the time since the previous one.
+    pub fn sample(&mut self, times: Vec<CpuTime>) {
+        if times.len() == self.prev.len() {
+            self.load = times
+                .iter()
+                .zip(&self.prev)
+                .map(|(now, prev)| {
+                    let total = now.total.saturating_sub(prev.total);
+                    let busy = now.busy.saturating_sub(prev.busy);
+                    if total == 0 {
+                        0.0
+                    } else {
+                        busy as f64 / total as f64
+                    }
+                })
+                .collect();
+        }
+        self.prev = times;
+    }
+
+    /// Returns the busy fraction of each CPU over the last interval, once two samples exist.
+    pub fn load(&self) -> Option<&[f64]> {
+        if self.load.is_empty() {
+            None
+        } else {
+            Some(&self.load)
+        }
+    }
+}
+
+/// Chooses a distinct CPU for each hot thread.
+pub trait AssignmentPolicy: Debug {
+    /// Returns `count` distinct CPUs, one for each hot thread in order.
+    fn assign(&self, count: usize, context: &PolicyContext) -> HcbResult<Vec<u32>>;
+
+    /// Returns true if the policy makes use of `PolicyContext::load`.
+    fn uses_load(&self) -> bool {
+        false
+    }
+}
+
+fn too_few(policy: &str, count: usize, available: usize) -> Error {
+    Error::Policy(format!(
+        "{} policy needs {} CPUs but only {} are available",
+        policy, count, available
+    ))
+}
+
+/// Picks one CPU from each of the first `count` cores, preferring the highest-numbered sibling
+/// so the lowest one is left for the other threads that share the core.
+fn one_per_core(policy: &str, cores: &[Vec<u32>], count: usize) -> HcbResult<Vec<u32>> {
+    if cores.len() < count {
+        return Err(too_few(policy, count, cores.len()));
+    }
+    Ok(cores[..count]
+        .iter()
+        .map(|siblings| *siblings.last().unwrap())
+        .collect())
+}
+
+/// Uses the listed CPUs in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExplicitCores(pub Vec<u32>);
+
+impl AssignmentPolicy for ExplicitCores {
+    fn assign(&self, count: usize, context: &PolicyContext) -> HcbResult<Vec<u32>> {
+        if self.0.len() < count {
+            return Err(too_few("Explicit core", count, self.0.len()));
+        }
+        let cpus = self.0[..count].to_vec();
+        for (i, &cpu) in cpus.iter().enumerate() {
+            if !context.is_allowed(cpu) {
+                return Err(Error::Policy(format!(
+                    "CPU {} is not available to the process",
+                    cpu
+                )));
+            }
+            if cpus[..i].contains(&cpu) {
+                return Err(Error::Policy(format!("CPU {} is listed more than once", cpu)));
+            }
+        }
+        Ok(cpus)
+    }
+}
+
+/// Gives each hot thread its own physical core.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PhysicalCores;
+
+impl AssignmentPolicy for PhysicalCores {
+    fn assign(&self, count: usize, context: &PolicyContext) -> HcbResult<Vec<u32>> {
+        one_per_core("Physical core", &context.allowed_cores(), count)
+    }
+}
+
+/// Gives each hot thread its own physical core, alternating between L3 cache domains.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SpreadCaches;
+
+impl AssignmentPolicy for SpreadCaches {
+    fn assign(&self, count: usize, context: &PolicyContext) -> HcbResult<Vec<u32>> {
+        let mut domains: Vec<(u32, Vec<Vec<u32>>)> = Vec::new();
+        for siblings in context.allowed_cores() {
+            let l3 = context.topology.cpu(siblings[0]).unwrap().l3;
+            match domains.iter_mut().find(|&&mut (domain, _)| domain == l3) {
+                Some(&mut (_, ref mut cores)) => cores.push(siblings),
+                None => domains.push((l3, vec![siblings])),
+            }
+        }
+        let depth = domains.iter().map(|(_, cores)| cores.len()).max();
+        let interleaved: Vec<_> = (0..depth.unwrap_or(0))
+            .flat_map(|i| domains.iter().filter_map(move |(_, cores)| cores.get(i)))
+            .cloned()
+            .collect();
+        one_per_core("Cache spreading", &interleaved, count)
+    }
+}
+
+/// Gives each hot thread its own physical core, choosing the least busy cores first.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct LeastLoaded;
+
+impl AssignmentPolicy for LeastLoaded {
+    fn assign(&self, count: usize, context: &PolicyContext) -> HcbResult<Vec<u32>> {
+        let mut cores = context.allowed_cores();
+        if let Some(load) = context.load {
+            let core_load = |siblings: &Vec<u32>| -> f64 {
+                siblings
+                    .iter()
+                    .map(|&cpu| load.get(cpu as usize).cloned().unwrap_or(0.0))
+                    .sum()
+            };
+            cores.sort_by(|l, r| core_load(l).partial_cmp(&core_load(r)).unwrap());
+        }
+        one_per_core("Least loaded", &cores, count)
+    }
+
+    fn uses_load(&self) -> bool {
+        true
+    }
+}
+
+/// The built-in policies, as selected on the command line.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Policy {
+    Cores(ExplicitCores),
+    #[default]
+    PhysicalCores,
+    SpreadCaches,
+    LeastLoaded,
+}
+
+impl Policy {
+    /// Returns the CPUs an explicit list assigns to `count` threads as an affinity mask, or
+    /// `None` for the policies which choose CPUs as they go.
+    pub fn listed(&self, count: usize) -> Option<usize> {
+        match *self {
+            Policy::Cores(ExplicitCores(ref cpus)) => Some(
+                cpus.iter()
+                    .take(count)
+                    .filter(|&&cpu| cpu < usize::BITS)
+                    .fold(0, |mask, &cpu| mask | 1 << cpu),
+            ),
+            _ => None,
+        }
+    }
+
+    fn inner(&self) -> &dyn AssignmentPolicy {
+        match *self {
+            Policy::Cores(ref cores) => cores,
+            Policy::PhysicalCores => &PhysicalCores,
+            Policy::SpreadCaches => &SpreadCaches,
+            Policy::LeastLoaded => &LeastLoaded,
+        }
+    }
+}
+
+impl AssignmentPolicy for Policy {
+    fn assign(&self, count: usize, context: &PolicyContext) -> HcbResult<Vec<u32>> {
+        self.inner().assign(count, context)
+    }
+
+    fn uses_load(&self) -> bool {
+        self.inner().uses_load()
+    }
+}
+
+impl FromStr for Policy {
+    type Err = Error;
+
+    /// Parses `physical`, `spread`, `least-loaded`, or a comma separated list of CPUs.
+    fn from_str(s: &str) -> HcbResult<Policy> {
+        match s {
+            "physical" => Ok(Policy::PhysicalCores),
+            "spread" => Ok(Policy::SpreadCaches),
+            "least-loaded" => Ok(Policy::LeastLoaded),
+            _ => s.split(',')
+                .map(|cpu| cpu.trim().parse())
+                .collect::<Result<Vec<u32>, _>>()
+                .map(|cpus| Policy::Cores(ExplicitCores(cpus)))
+                .map_err(|_| Error::Policy(format!("Unknown policy '{}'", s))),
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Policy::Cores(ExplicitCores(ref cpus)) => {
+                let cpus: Vec<_> = cpus.iter().map(|cpu| cpu.to_string()).collect();
+                write!(f, "{}", cpus.join(","))
+            }
+            Policy::PhysicalCores => write!(f, "physical"),
+            Policy::SpreadCaches => write!(f, "spread"),
+            Policy::LeastLoaded => write!(f, "least-loaded"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use topology::Cpu;
+
+    fn context(topology: &Topology) -> PolicyContext<'_> {
+        PolicyContext {
+            topology,
+            allowed: !0,
+            load: None,
+        }
+    }
+
+    /// Two packages of two cores each, with siblings numbered N and N + 4.
+    fn two_packages() -> Topology {
+        Topology::new(
+            (0..8)
+                .map(|id| Cpu {
+                    id,
+                    core: id % 4,
+                    package: id % 4 / 2,
+                    l2: id % 4,
+                    l3: id % 4 / 2,
+                    node: id % 4 / 2,
+                    efficiency: false,
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn physical_cores_match_odd_cpus_with_adjacent_siblings() {
+        let topology = Topology::uniform(12, 2);
+        let cpus = PhysicalCores.assign(3, &context(&topology)).unwrap();
+        assert_eq!(cpus, vec![1, 3, 5]);
+    }
+
+    #[test]
+    fn physical_cores_without_smt() {
+        let topology = Topology::uniform(4, 1);
+        let cpus = PhysicalCores.assign(3, &context(&topology)).unwrap();
+        assert_eq!(cpus, vec![0, 1, 2]);
+        assert!(PhysicalCores.assign(5, &context(&topology)).is_err());
+    }
+
+    #[test]
+    fn physical_cores_respect_affinity() {
+        let topology = Topology::uniform(8, 2);
+        let context = PolicyContext {
+            allowed: 0b1111_0101,
+            ..context(&topology)
+        };
+        assert_eq!(PhysicalCores.assign(3, &context).unwrap(), vec![0, 2, 5]);
+    }
+
+    #[test]
+    fn spreads_across_caches() {
+        let topology = two_packages();
+        let cpus = SpreadCaches.assign(3, &context(&topology)).unwrap();
+        assert_eq!(cpus, vec![4, 6, 5]);
+    }
+
+    #[test]
+    fn prefers_least_loaded_cores() {
+        let topology = Topology::uniform(8, 2);
+        let load = [0.9, 0.9, 0.1, 0.0, 0.5, 0.5, 0.2, 0.2];
+        let context = PolicyContext {
+            load: Some(&load),
+            ..context(&topology)
+        };
+        assert_eq!(LeastLoaded.assign(2, &context).unwrap(), vec![3, 7]);
+    }
+
+    #[test]
+    fn measures_load_between_samples() {
+        let mut meter = LoadMeter::new();
+        let sample = |busy: u64, total: u64| CpuTime { busy, total };
+        meter.sample(vec![sample(10, 100), sample(50, 100)]);
+        assert_eq!(meter.load(), None);
+        meter.sample(vec![sample(60, 200), sample(50, 200)]);
+        assert_eq!(meter.load(), Some(&[0.5, 0.0][..]));
+    }
+
+    #[test]
+    fn validates_explicit_cores() {
+        let topology = Topology::uniform(8, 2);
+        let policy = ExplicitCores(vec![2, 4, 2]);
+        assert_eq!(policy.assign(2, &context(&topology)).unwrap(), vec![2, 4]);
+        assert!(policy.assign(3, &context(&topology)).is_err());
+        let context = PolicyContext {
+            allowed: 0b11,
+            ..context(&topology)
+        };
+        assert!(policy.assign(1, &context).is_err());
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!("spread".parse::<Policy>().unwrap(), Policy::SpreadCaches);
+        let cores = "1, 3,5".parse::<Policy>().unwrap();
+        assert_eq!(cores, Policy::Cores(ExplicitCores(vec![1, 3, 5])));
+        assert_eq!(cores.to_string(), "1,3,5");
+        assert_eq!(cores.listed(2), Some(0b1010));
+        assert_eq!(Policy::PhysicalCores.listed(2), None);
+        assert!("fastest".parse::<Policy>().is_err());
+    }
+}
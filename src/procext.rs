@@ -1,112 +1,516 @@
 use std::collections::{HashMap, HashSet, hash_map::Entry};
+use std::time::Instant;
 
-use win::{Process, Thread};
+use backend::{OsProcess, OsThread};
+use history::{History, Sample};
 use {Error, HcbResult};
 
+/// How thread activity is smoothed before threads are ranked.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Smoothing {
+    /// The weight of the newest sample in the exponentially weighted moving average, in (0, 1].
+    /// 1 ranks threads by the last poll alone.
+    pub alpha: f64,
+    /// How much more active than the weakest current hot thread another thread must be to
+    /// displace it, as a fraction of that thread's activity.
+    pub hysteresis: f64,
+}
+
+impl Smoothing {
+    pub fn new(alpha: f64, hysteresis: f64) -> HcbResult<Smoothing> {
+        if !(alpha > 0.0 && alpha <= 1.0) {
+            return Err(Error::Config(format!(
+                "Smoothing factor {} is not in the range (0, 1]",
+                alpha
+            )));
+        }
+        if hysteresis.is_nan() || hysteresis < 0.0 {
+            return Err(Error::Config(format!(
+                "Hysteresis margin {} is negative",
+                hysteresis
+            )));
+        }
+        Ok(Smoothing { alpha, hysteresis })
+    }
+}
+
+impl Default for Smoothing {
+    /// Ranks threads by the last poll alone, with no hysteresis.
+    fn default() -> Smoothing {
+        Smoothing {
+            alpha: 1.0,
+            hysteresis: 0.0,
+        }
+    }
+}
+
+/// How many samples each thread's history keeps by default.
+pub const DEFAULT_HISTORY: usize = 120;
+
 #[derive(Debug)]
-pub struct MonitoredProcess {
-    process: Process,
-    threads: HashMap<u32, MonitoredThread>,
+pub struct MonitoredProcess<P: OsProcess> {
+    process: P,
+    /// Other processes whose threads are monitored alongside the process's own.
+    descendants: Vec<P>,
+    threads: HashMap<u32, MonitoredThread<P::Thread>>,
     thread_ids: HashSet<u32>,
     thread_activity: Vec<u32>,
+    smoothing: Smoothing,
+    /// The threads last returned by `hot_threads`.
+    hot: Vec<u32>,
+    /// Threads which `hot_threads` may not return.
+    excluded: Vec<u32>,
+    history_capacity: usize,
 }
 
-impl MonitoredProcess {
-    pub fn new(process: Process) -> HcbResult<MonitoredProcess> {
+impl<P: OsProcess> MonitoredProcess<P> {
+    /// Starts monitoring a process, taking the first sample of its threads at `now`.
+    pub fn new(process: P, now: Instant) -> HcbResult<MonitoredProcess<P>> {
         let mut mproc = MonitoredProcess {
             process,
+            descendants: Vec::new(),
             threads: HashMap::new(),
             thread_ids: HashSet::new(),
             thread_activity: Vec::new(),
+            smoothing: Smoothing::default(),
+            hot: Vec::new(),
+            excluded: Vec::new(),
+            history_capacity: DEFAULT_HISTORY,
         };
-        mproc.update()?;
+        mproc.update_at(now)?;
         Ok(mproc)
     }
 
-    pub fn process(&self) -> &Process {
+    pub fn process(&self) -> &P {
         &self.process
     }
 
-    pub fn process_mut(&mut self) -> &mut Process {
+    pub fn process_mut(&mut self) -> &mut P {
         &mut self.process
     }
 
-    pub fn threads(&self) -> &HashMap<u32, MonitoredThread> {
+    /// Returns the descendants whose threads are monitored alongside the process's own.
+    pub fn descendants(&self) -> &[P] {
+        &self.descendants
+    }
+
+    /// Monitors the threads of `descendants` alongside the process's own from the next update
+    /// on, so they are ranked together and any of them may be chosen as a hot thread.
+    pub fn set_descendants(&mut self, descendants: Vec<P>) {
+        self.descendants = descendants;
+    }
+
+    /// Monitors the threads of another descendant from the next update on, unless it already is.
+    /// Returns true if it was added.
+    pub fn add_descendant(&mut self, descendant: P) -> bool {
+        if self.process_ids().contains(&descendant.id()) {
+            return false;
+        }
+        self.descendants.push(descendant);
+        true
+    }
+
+    /// Returns the ids of the process and its monitored descendants.
+    pub fn process_ids(&self) -> Vec<u32> {
+        let descendants = self.descendants.iter().map(|p| p.id());
+        Some(self.process.id()).into_iter().chain(descendants).collect()
+    }
+
+    pub fn threads(&self) -> &HashMap<u32, MonitoredThread<P::Thread>> {
         &self.threads
     }
 
-    pub fn threads_mut(&mut self) -> &mut HashMap<u32, MonitoredThread> {
+    pub fn threads_mut(&mut self) -> &mut HashMap<u32, MonitoredThread<P::Thread>> {
         &mut self.threads
     }
 
+    /// Keeps the threads `ids` from being picked as hot threads, such as those placed by rules.
+    pub fn exclude(&mut self, ids: &[u32]) {
+        self.excluded = ids.to_vec();
+    }
+
+    /// Changes how activity is smoothed from the next update on.
+    pub fn set_smoothing(&mut self, smoothing: Smoothing) {
+        self.smoothing = smoothing;
+    }
+
+    /// Changes how many samples each thread's history keeps.
+    pub fn set_history_capacity(&mut self, capacity: usize) {
+        self.history_capacity = capacity;
+        for thread in self.threads.values_mut() {
+            thread.history.set_capacity(capacity);
+        }
+    }
+
+    /// Returns the fraction of the activity of the process's current threads since `since` which
+    /// was used by the thread `id`.
+    ///
+    /// Threads which have exited no longer count towards the process's activity.
+    pub fn share(&self, id: u32, since: Instant) -> Option<f64> {
+        let thread = self.threads.get(&id)?.history().total(since);
+        let total: u64 = self.threads
+            .values()
+            .map(|thread| thread.history().total(since))
+            .sum();
+        if total == 0 {
+            None
+        } else {
+            Some(thread as f64 / total as f64)
+        }
+    }
+
+    /// Returns the thread ids ordered by smoothed activity, most active first.
     pub fn thread_ids_by_activity(&self) -> &[u32] {
         &self.thread_activity
     }
 
+    /// Returns the `count` most active threads, most active first, or `None` if the process has
+    /// fewer threads which are not excluded.
+    ///
+    /// A thread which was returned by the previous call is only displaced by one whose smoothed
+    /// activity exceeds its own by the hysteresis margin.
+    pub fn hot_threads(&mut self, count: usize) -> Option<Vec<u32>> {
+        let excluded = &self.excluded;
+        let candidates: Vec<u32> = self.thread_activity
+            .iter()
+            .filter(|id| !excluded.contains(id))
+            .cloned()
+            .collect();
+        if candidates.len() < count {
+            return None;
+        }
+        let threads = &self.threads;
+        let activity = |id: &u32| threads[id].smoothed();
+        let mut hot: Vec<u32> = self.hot
+            .iter()
+            .filter(|id| threads.contains_key(id) && !excluded.contains(id))
+            .cloned()
+            .collect();
+        hot.truncate(count);
+        for &id in &candidates {
+            if hot.contains(&id) {
+                continue;
+            }
+            if hot.len() < count {
+                hot.push(id);
+                continue;
+            }
+            let weakest = (0..hot.len())
+                .min_by(|&l, &r| activity(&hot[l]).partial_cmp(&activity(&hot[r])).unwrap())
+                .unwrap();
+            if activity(&id) > activity(&hot[weakest]) * (1.0 + self.smoothing.hysteresis) {
+                hot[weakest] = id;
+            } else {
+                break;
+            }
+        }
+        hot.sort_by(|l, r| activity(r).partial_cmp(&activity(l)).unwrap());
+        self.hot = hot.clone();
+        Some(hot)
+    }
+
+    /// Samples every thread's activity, timestamping the samples with the current time.
     pub fn update(&mut self) -> HcbResult<()> {
+        self.update_at(Instant::now())
+    }
+
+    /// Samples every thread's activity, timestamping the samples with `now`.
+    ///
+    /// Descendants which have exited stop being monitored.
+    pub fn update_at(&mut self, now: Instant) -> HcbResult<()> {
         self.thread_ids.clear();
         self.thread_activity.clear();
+        self.descendants.retain(|p| p.running());
         if !self.process.running() {
-            self.threads.clear();
-            return Err(Error::NoProcess);
+            // Threads of descendants are kept so their placement can still be restored.
+            if self.descendants.is_empty() {
+                self.threads.clear();
+            }
+            return Err(Error::ProcessExited(self.process.id()));
         }
-        for thread_id in self.process.thread_ids()? {
-            let thread_updated = MonitoredProcess::get_or_add_thread(self.threads.entry(thread_id))
-                .and_then(|thread| thread.update());
-            if let Ok(_) = thread_updated {
-                self.thread_ids.insert(thread_id);
-                self.thread_activity.push(thread_id);
+        self.sample(None, now)?;
+        for index in 0..self.descendants.len() {
+            if let Err(e) = self.sample(Some(index), now) {
+                debug!(
+                    "Could not sample process {}: {}",
+                    self.descendants[index].id(),
+                    e
+                );
             }
         }
         let thread_ids = &self.thread_ids;
         self.threads.retain(|id, _| thread_ids.contains(id));
         let threads = &self.threads;
-        self.thread_activity
-            .sort_unstable_by(|lt_id, rt_id| threads[rt_id].delta().cmp(&threads[lt_id].delta()));
+        self.thread_activity.sort_unstable_by(|lt_id, rt_id| {
+            threads[rt_id]
+                .smoothed()
+                .partial_cmp(&threads[lt_id].smoothed())
+                .unwrap()
+        });
+        Ok(())
+    }
+
+    /// Samples the threads of the process, or of the descendant at `descendant`.
+    fn sample(&mut self, descendant: Option<usize>, now: Instant) -> HcbResult<()> {
+        let process = match descendant {
+            Some(index) => &self.descendants[index],
+            None => &self.process,
+        };
+        let alpha = self.smoothing.alpha;
+        let capacity = self.history_capacity;
+        for thread_id in process.thread_ids()? {
+            let thread_updated =
+                MonitoredProcess::get_or_add_thread(process, self.threads.entry(thread_id))
+                    .and_then(|thread| {
+                        thread.history.set_capacity(capacity);
+                        thread.update(now)?;
+                        thread.smooth(alpha);
+                        Ok(())
+                    });
+            if thread_updated.is_ok() {
+                self.thread_ids.insert(thread_id);
+                self.thread_activity.push(thread_id);
+            }
+        }
         Ok(())
     }
 
+    /// Restores the original placement of every thread which was changed, skipping threads
+    /// which can no longer be changed.
+    pub fn restore(&mut self) {
+        let mut ids: Vec<u32> = self.threads
+            .iter()
+            .filter(|&(_, thread)| thread.changed())
+            .map(|(&id, _)| id)
+            .collect();
+        ids.sort_unstable();
+        for id in ids {
+            if let Err(e) = self.threads.get_mut(&id).unwrap().restore() {
+                debug!("Could not restore thread {}: {}", id, e);
+            }
+        }
+    }
+
     fn get_or_add_thread<'a>(
-        entry: Entry<'a, u32, MonitoredThread>,
-    ) -> HcbResult<&'a mut MonitoredThread> {
-        let id = *entry.key();
-        Ok(entry.or_insert(MonitoredThread::new(Thread::from_id(id)?)?))
+        process: &P,
+        entry: Entry<'a, u32, MonitoredThread<P::Thread>>,
+    ) -> HcbResult<&'a mut MonitoredThread<P::Thread>> {
+        match entry {
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+            Entry::Vacant(entry) => {
+                let thread = MonitoredThread::new(process.thread(*entry.key())?)?;
+                Ok(entry.insert(thread))
+            }
+        }
     }
 }
 
 #[derive(Debug)]
-pub struct MonitoredThread {
-    thread: Thread,
+pub struct MonitoredThread<T: OsThread> {
+    thread: T,
     cycles: u64,
     delta: u64,
+    smoothed: f64,
+    history: History,
+    /// The ideal processor the thread had before it was first changed.
+    original_ideal: Option<u32>,
+    /// The affinity mask the thread had before it was first changed.
+    original_affinity: Option<usize>,
 }
 
-impl MonitoredThread {
-    pub fn new(thread: Thread) -> HcbResult<MonitoredThread> {
-        let cycles = thread.cycle_time()?;
+impl<T: OsThread> MonitoredThread<T> {
+    pub fn new(thread: T) -> HcbResult<MonitoredThread<T>> {
+        let cycles = thread.cpu_counter()?;
         Ok(MonitoredThread {
             thread,
             cycles,
             delta: 0,
+            smoothed: 0.0,
+            history: History::new(DEFAULT_HISTORY),
+            original_ideal: None,
+            original_affinity: None,
         })
     }
 
-    pub fn update(&mut self) -> HcbResult<u64> {
-        let new_cycles = self.thread.cycle_time()?;
+    /// Samples the thread's activity since the last update, recording it as taken at `now`.
+    pub fn update(&mut self, now: Instant) -> HcbResult<u64> {
+        let new_cycles = self.thread.cpu_counter()?;
         self.delta = new_cycles - self.cycles;
         self.cycles = new_cycles;
+        self.history.push(Sample {
+            at: now,
+            delta: self.delta,
+        });
         Ok(self.delta)
     }
 
-    pub fn thread(&self) -> &Thread {
+    pub fn thread(&self) -> &T {
         &self.thread
     }
 
-    pub fn thread_mut(&mut self) -> &mut Thread {
+    pub fn thread_mut(&mut self) -> &mut T {
         &mut self.thread
     }
 
     pub fn delta(&self) -> u64 {
         self.delta
     }
+
+    /// Folds the latest delta into the moving average, giving it a weight of `alpha`.
+    pub fn smooth(&mut self, alpha: f64) -> f64 {
+        self.smoothed = alpha * self.delta as f64 + (1.0 - alpha) * self.smoothed;
+        self.smoothed
+    }
+
+    /// Returns the exponentially weighted moving average of the deltas.
+    pub fn smoothed(&self) -> f64 {
+        self.smoothed
+    }
+
+    /// Returns the deltas of the most recent updates.
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Returns true if the thread's placement has been changed since it was last restored.
+    pub fn changed(&self) -> bool {
+        self.original_ideal.is_some() || self.original_affinity.is_some()
+    }
+
+    /// Puts back the ideal processor and affinity mask the thread had before they were first
+    /// changed through this handle.
+    pub fn restore(&mut self) -> HcbResult<()> {
+        if let Some(ideal) = self.original_ideal.take() {
+            self.thread.set_ideal_processor(ideal)?;
+        }
+        // Restored last, as some backends emulate the ideal processor with the affinity mask.
+        if let Some(affinity) = self.original_affinity.take() {
+            self.thread.set_affinity_mask(affinity)?;
+        }
+        Ok(())
+    }
+}
+
+/// Placement changes made through a monitored thread are recorded so they can be restored.
+impl<T: OsThread> OsThread for MonitoredThread<T> {
+    fn id(&self) -> u32 {
+        self.thread.id()
+    }
+
+    fn name(&self) -> HcbResult<Option<String>> {
+        self.thread.name()
+    }
+
+    fn cpu_counter(&self) -> HcbResult<u64> {
+        self.thread.cpu_counter()
+    }
+
+    fn ideal_processor(&self) -> HcbResult<u32> {
+        self.thread.ideal_processor()
+    }
+
+    fn set_ideal_processor(&mut self, processor: u32) -> HcbResult<u32> {
+        if self.original_affinity.is_none() {
+            self.original_affinity = Some(self.thread.affinity_mask()?);
+        }
+        let prev = self.thread.set_ideal_processor(processor)?;
+        self.original_ideal.get_or_insert(prev);
+        Ok(prev)
+    }
+
+    fn emulates_ideal_processor(&self) -> bool {
+        self.thread.emulates_ideal_processor()
+    }
+
+    fn affinity_mask(&self) -> HcbResult<usize> {
+        self.thread.affinity_mask()
+    }
+
+    fn set_affinity_mask(&mut self, mask: usize) -> HcbResult<usize> {
+        let prev = self.thread.set_affinity_mask(mask)?;
+        self.original_affinity.get_or_insert(prev);
+        Ok(prev)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::Backend;
+    use mock::{Frame, MockBackend, MockProcess};
+    use std::time::Duration;
+
+    /// Monitors a process playing through `frames`, starting in the first.
+    fn monitor(
+        frames: &[&[(u32, u64)]],
+        smoothing: Smoothing,
+    ) -> (MockBackend, MonitoredProcess<MockProcess>) {
+        let backend = MockBackend::new();
+        let script = frames.iter().map(|activity| Frame::from_activity(activity)).collect();
+        backend.add_process(1, "game", 0xff, script);
+        let process = backend.processes().unwrap().remove(0);
+        let mut process = MonitoredProcess::new(process, Instant::now()).unwrap();
+        process.set_smoothing(smoothing);
+        (backend, process)
+    }
+
+    #[test]
+    fn smoothing_ignores_a_single_spike() {
+        let steady: &[(u32, u64)] = &[(1, 100), (2, 10)];
+        let spike: &[(u32, u64)] = &[(1, 0), (2, 150)];
+        let mut frames = vec![steady; 8];
+        frames.push(spike);
+        let (backend, mut process) = monitor(&frames, Smoothing::new(0.3, 0.0).unwrap());
+        for _ in 0..8 {
+            backend.tick();
+            process.update().unwrap();
+        }
+        assert_eq!(process.thread_ids_by_activity(), &[1, 2]);
+        assert_eq!(process.hot_threads(1), Some(vec![1]));
+    }
+
+    #[test]
+    fn incumbents_resist_close_challengers() {
+        let first: &[(u32, u64)] = &[(1, 100), (2, 90), (3, 0)];
+        let close: &[(u32, u64)] = &[(1, 100), (2, 90), (3, 95)];
+        let clear: &[(u32, u64)] = &[(1, 100), (2, 90), (3, 120)];
+        let frames = [first, first, close, clear];
+        let (backend, mut process) = monitor(&frames, Smoothing::new(1.0, 0.2).unwrap());
+        backend.tick();
+        process.update().unwrap();
+        assert_eq!(process.hot_threads(2), Some(vec![1, 2]));
+        backend.tick();
+        process.update().unwrap();
+        assert_eq!(process.thread_ids_by_activity(), &[1, 3, 2]);
+        assert_eq!(process.hot_threads(2), Some(vec![1, 2]));
+        backend.tick();
+        process.update().unwrap();
+        assert_eq!(process.hot_threads(2), Some(vec![3, 1]));
+        assert_eq!(process.hot_threads(4), None);
+    }
+
+    #[test]
+    fn reports_share_of_process_over_a_window() {
+        let first: &[(u32, u64)] = &[(1, 30), (2, 10)];
+        let second: &[(u32, u64)] = &[(1, 10), (2, 50)];
+        let (backend, mut process) = monitor(&[first, first, second], Smoothing::default());
+        let start = Instant::now();
+        backend.tick();
+        process.update_at(start).unwrap();
+        backend.tick();
+        process.update_at(start + Duration::from_secs(1)).unwrap();
+        assert_eq!(process.share(1, start), Some(0.4));
+        assert_eq!(process.share(2, start + Duration::from_secs(1)), Some(50.0 / 60.0));
+        assert_eq!(process.threads()[&1].history().len(), 3);
+        process.set_history_capacity(1);
+        assert_eq!(process.share(1, start), Some(10.0 / 60.0));
+        assert_eq!(process.share(3, start), None);
+    }
+
+    #[test]
+    fn validates_smoothing() {
+        assert!(Smoothing::new(0.0, 0.0).is_err());
+        assert!(Smoothing::new(1.5, 0.0).is_err());
+        assert!(Smoothing::new(0.5, -0.1).is_err());
+    }
 }
@@ -0,0 +1,84 @@
+//! Noticing changes to the configuration file.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use HcbResult;
+
+/// A source of changes to one file.
+pub trait FileWatcher {
+    /// Returns true if the file has changed since the last check, without waiting.
+    ///
+    /// Creating, replacing and deleting the file all count as changes.
+    fn changed(&mut self) -> HcbResult<bool>;
+}
+
+impl<W: FileWatcher + ?Sized> FileWatcher for Box<W> {
+    fn changed(&mut self) -> HcbResult<bool> {
+        (**self).changed()
+    }
+}
+
+/// Finds changes by comparing the file's modification time and size at each check.
+///
+/// This works on every platform, but a change which keeps both within the timestamp
+/// granularity of the file system goes unnoticed.
+#[derive(Debug)]
+pub struct ModifiedWatcher {
+    path: PathBuf,
+    stamp: Option<(SystemTime, u64)>,
+}
+
+impl ModifiedWatcher {
+    pub fn new(path: &Path) -> HcbResult<ModifiedWatcher> {
+        Ok(ModifiedWatcher {
+            path: path.to_owned(),
+            stamp: ModifiedWatcher::stamp(path)?,
+        })
+    }
+
+    /// Returns the file's modification time and size, or `None` if there is no file.
+    fn stamp(path: &Path) -> HcbResult<Option<(SystemTime, u64)>> {
+        match fs::metadata(path) {
+            Ok(metadata) => Ok(Some((metadata.modified()?, metadata.len()))),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl FileWatcher for ModifiedWatcher {
+    fn changed(&mut self) -> HcbResult<bool> {
+        let stamp = ModifiedWatcher::stamp(&self.path)?;
+        if stamp == self.stamp {
+            return Ok(false);
+        }
+        self.stamp = stamp;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn notices_files_being_created_changed_and_deleted() {
+        let path = env::temp_dir().join(format!("rlhcbfix-reload-{}.toml", process::id()));
+        let _ = fs::remove_file(&path);
+        let mut watcher = ModifiedWatcher::new(&path).unwrap();
+        assert!(!watcher.changed().unwrap());
+        fs::write(&path, "poll = 1\n").unwrap();
+        assert!(watcher.changed().unwrap());
+        assert!(!watcher.changed().unwrap());
+        fs::write(&path, "poll = 10\n").unwrap();
+        assert!(watcher.changed().unwrap());
+        fs::remove_file(&path).unwrap();
+        assert!(watcher.changed().unwrap());
+        assert!(!watcher.changed().unwrap());
+    }
+}
@@ -0,0 +1,233 @@
+//! Keeping every other process off the CPUs reserved for the target's hot threads.
+
+use std::process;
+
+use backend::{Backend, OsProcess, OsThread};
+use watch::ProcessEvent;
+use HcbResult;
+
+/// A thread moved off the reserved CPUs.
+#[derive(Debug)]
+struct MovedThread<T> {
+    thread: T,
+    /// The mask the thread had before it was first moved.
+    original: usize,
+    /// The mask the thread was last given.
+    applied: usize,
+}
+
+/// A process whose threads were moved off the reserved CPUs.
+#[derive(Debug)]
+struct Moved<P: OsProcess> {
+    process: P,
+    threads: Vec<MovedThread<P::Thread>>,
+}
+
+/// Restricts the threads of the target's owner's other processes to the unreserved CPUs, and
+/// restores each thread's original affinity mask when dropped.
+///
+/// Each thread keeps whatever CPUs it had apart from the reserved ones, so threads which were
+/// already held on particular CPUs stay apart. Processes are moved when the CPUs are reserved
+/// and as the watcher reports them starting. Threads a moved process starts later inherit the
+/// mask of the thread which started them on Linux, but on Windows they are only moved when the
+/// CPUs are next reserved.
+///
+/// Processes of other owners and of the operating system itself, such as kernel threads,
+/// system daemons and services, are left alone, as are processes and threads which cannot be
+/// opened or changed.
+#[derive(Debug)]
+pub struct Reservation<P: OsProcess> {
+    target: u32,
+    /// The target's owner, whose other processes are moved.
+    owner: Option<u32>,
+    /// Other processes which must not be moved.
+    exempt: Vec<u32>,
+    reserved: usize,
+    moved: Vec<Moved<P>>,
+}
+
+impl<P: OsProcess> Reservation<P> {
+    /// Creates a reservation on behalf of the process `target`, reserving nothing.
+    pub fn new(target: &P) -> HcbResult<Reservation<P>> {
+        Ok(Reservation {
+            target: target.id(),
+            owner: target.owner()?,
+            exempt: Vec::new(),
+            reserved: 0,
+            moved: Vec::new(),
+        })
+    }
+
+    /// Keeps the processes `ids` from being moved from now on, such as other managed targets,
+    /// giving those which were already moved their original affinity masks back.
+    pub fn exempt(&mut self, ids: &[u32]) {
+        self.exempt = ids.to_vec();
+        let (exempt, moved) = self.moved
+            .drain(..)
+            .partition(|moved| ids.contains(&moved.process.id()));
+        self.moved = moved;
+        for mut moved in exempt {
+            restore_threads(&mut moved);
+        }
+    }
+
+    /// Reserves the CPUs in `reserved`, moving the threads of every other process off them.
+    pub fn reserve<B: Backend<Process = P>>(
+        &mut self,
+        backend: &B,
+        reserved: usize,
+    ) -> HcbResult<()> {
+        self.reserved = reserved;
+        self.moved.retain(|moved| moved.process.running());
+        for moved in &mut self.moved {
+            move_threads(&moved.process, &mut moved.threads, reserved);
+        }
+        self.move_all(backend)
+    }
+
+    /// Moves the processes `events` report starting off the reserved CPUs, and forgets those
+    /// which have exited. If events were lost, every process is looked at again.
+    pub fn update<B: Backend<Process = P>>(
+        &mut self,
+        backend: &B,
+        events: &[ProcessEvent],
+    ) -> HcbResult<()> {
+        if self.reserved == 0 {
+            return Ok(());
+        }
+        for &event in events {
+            match event {
+                ProcessEvent::Started(id) => {
+                    if let Ok(process) = backend.process(id) {
+                        self.move_process(process);
+                    }
+                }
+                ProcessEvent::Exited(id) => self.moved.retain(|moved| moved.process.id() != id),
+                ProcessEvent::Overflow => {
+                    self.moved.retain(|moved| moved.process.running());
+                    self.move_all(backend)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn move_all<B: Backend<Process = P>>(&mut self, backend: &B) -> HcbResult<()> {
+        for process in backend.processes()? {
+            self.move_process(process);
+        }
+        Ok(())
+    }
+
+    /// Moves the threads of a process which has not been moved yet off the reserved CPUs.
+    fn move_process(&mut self, process: P) {
+        let id = process.id();
+        if id == self.target
+            || id == process::id()
+            || self.exempt.contains(&id)
+            || self.moved.iter().any(|m| m.process.id() == id)
+        {
+            return;
+        }
+        match process.owner() {
+            Ok(owner) if owner.is_some() && owner == self.owner => {}
+            _ => return,
+        }
+        let mut threads = Vec::new();
+        move_threads(&process, &mut threads, self.reserved);
+        if !threads.is_empty() {
+            self.moved.push(Moved { process, threads });
+        }
+    }
+
+    /// Gives every moved thread which is still running its original affinity mask back.
+    pub fn restore(&mut self) {
+        if !self.moved.is_empty() {
+            info!("Restoring the affinity of {} processes.", self.moved.len());
+        }
+        for mut moved in self.moved.drain(..) {
+            restore_threads(&mut moved);
+        }
+        self.reserved = 0;
+    }
+}
+
+/// Gives the moved threads of a process which is still running their original masks back.
+fn restore_threads<P: OsProcess>(moved: &mut Moved<P>) {
+    if !moved.process.running() {
+        return;
+    }
+    for moved_thread in &mut moved.threads {
+        if let Err(e) = moved_thread.thread.set_affinity_mask(moved_thread.original) {
+            debug!(
+                "Could not restore thread {} of process {}: {}",
+                moved_thread.thread.id(),
+                moved.process.id(),
+                e
+            );
+        }
+    }
+}
+
+impl<P: OsProcess> Drop for Reservation<P> {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+/// Moves every thread of `process` off the `reserved` CPUs, recording each thread's original
+/// mask in `threads` before it is first changed.
+fn move_threads<P: OsProcess>(
+    process: &P,
+    threads: &mut Vec<MovedThread<P::Thread>>,
+    reserved: usize,
+) {
+    let ids = match process.thread_ids() {
+        Ok(ids) => ids,
+        Err(e) => {
+            debug!("Could not move process {}: {}", process.id(), e);
+            return;
+        }
+    };
+    threads.retain(|moved| ids.contains(&moved.thread.id()));
+    for id in ids {
+        let index = match threads.iter().position(|moved| moved.thread.id() == id) {
+            Some(index) => index,
+            None => {
+                let thread = match process.thread(id) {
+                    Ok(thread) => thread,
+                    Err(_) => continue,
+                };
+                let original = match thread.affinity_mask() {
+                    Ok(mask) => mask,
+                    Err(_) => continue,
+                };
+                threads.push(MovedThread {
+                    thread,
+                    original,
+                    applied: original,
+                });
+                threads.len() - 1
+            }
+        };
+        let moved = &mut threads[index];
+        let mask = restricted(moved.original, reserved);
+        if mask == moved.applied {
+            continue;
+        }
+        match moved.thread.set_affinity_mask(mask) {
+            Ok(_) => moved.applied = mask,
+            Err(e) => debug!("Could not move thread {} of process {}: {}", id, process.id(), e),
+        }
+    }
+    // Threads which were never changed need no restoring, and are looked at afresh next time.
+    threads.retain(|moved| moved.applied != moved.original);
+}
+
+/// Removes the reserved CPUs from a mask, unless that would leave the thread nowhere to run.
+fn restricted(original: usize, reserved: usize) -> usize {
+    match original & !reserved {
+        0 => original,
+        mask => mask,
+    }
+}
@@ -0,0 +1,646 @@
+//! Rules which hold particular threads on particular CPUs, alongside the hot thread placement.
+//!
+//! A rule is written `<matcher> -> <target>`, where the matcher is one of
+//!
+//! * `name:<pattern>`, threads whose name matches the shell-style wildcard pattern,
+//! * `rank:<n>`, the thread ranked `n` by smoothed activity, 1 being the most active, where
+//!   the threads placed as hot threads always rank first,
+//! * `share<<n>%` or `share><n>%`, threads using less or more than `n` percent of the process's
+//!   smoothed activity,
+//!
+//! and the target is one of
+//!
+//! * a CPU list such as `4` or `8-11,14`,
+//! * `physical`, one CPU of each physical core, so the thread never shares a core with an SMT
+//!   sibling of another CPU it may use,
+//! * `physical:l3=<n>`, the same within the L3 cache domain identified by its lowest CPU,
+//! * `efficiency` or `performance`, the CPUs of a hybrid processor's efficiency or performance
+//!   cores.
+//!
+//! For example `name:RenderThread* -> 4`, `rank:4 -> physical:l3=0` or
+//! `share<1% -> efficiency`. Each thread is held by the first rule it matches. Threads held by
+//! a rule are never picked as hot threads, and rules never use the CPUs the hot threads were
+//! assigned. The CPUs rules hold threads on are claimed alongside the hot threads', so other
+//! profiles keep off them. A profile is rejected if its rules conflict with each other, with
+//! its hot threads or with the CPUs other profiles hold.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+
+use glob;
+
+use backend::{OsProcess, OsThread};
+use procext::MonitoredProcess;
+use topology::{self, Topology};
+use {Error, HcbResult};
+
+/// Formats an affinity mask as a CPU list such as `0-3,8`.
+pub fn cpu_list(mask: usize) -> String {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for cpu in (0..usize::BITS).filter(|cpu| mask & (1 << cpu) != 0) {
+        match ranges.last_mut() {
+            Some(&mut (_, ref mut last)) if *last + 1 == cpu => *last = cpu,
+            _ => ranges.push((cpu, cpu)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(first, last)| match last - first {
+            0 => first.to_string(),
+            1 => format!("{},{}", first, last),
+            _ => format!("{}-{}", first, last),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Returns the affinity mask of the CPUs.
+fn mask<I: Iterator<Item = u32>>(cpus: I) -> usize {
+    cpus.filter(|&cpu| cpu < usize::BITS)
+        .fold(0, |mask, cpu| mask | 1 << cpu)
+}
+
+/// Which threads a rule applies to.
+#[derive(Debug, Clone)]
+pub enum Matcher {
+    /// Threads whose name matches the pattern.
+    Name(glob::Pattern),
+    /// The thread with this rank by smoothed activity, counting from 1.
+    Rank(usize),
+    /// Threads using less than this percentage of the process's smoothed activity.
+    ShareBelow(f64),
+    /// Threads using more than this percentage of the process's smoothed activity.
+    ShareAbove(f64),
+}
+
+/// What is known about a thread when the rules are evaluated.
+#[derive(Debug, Clone, PartialEq)]
+struct Facts {
+    name: Option<String>,
+    rank: usize,
+    /// The thread's percentage of the process's smoothed activity, if there was any activity.
+    share: Option<f64>,
+}
+
+impl Matcher {
+    fn matches(&self, facts: &Facts) -> bool {
+        match *self {
+            Matcher::Name(ref pattern) => facts
+                .name
+                .as_ref()
+                .is_some_and(|name| pattern.matches(name)),
+            Matcher::Rank(rank) => facts.rank == rank,
+            Matcher::ShareBelow(percent) => facts.share.is_some_and(|share| share < percent),
+            Matcher::ShareAbove(percent) => facts.share.is_some_and(|share| share > percent),
+        }
+    }
+}
+
+impl fmt::Display for Matcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Matcher::Name(ref pattern) => write!(f, "name:{}", pattern),
+            Matcher::Rank(rank) => write!(f, "rank:{}", rank),
+            Matcher::ShareBelow(percent) => write!(f, "share<{}%", percent),
+            Matcher::ShareAbove(percent) => write!(f, "share>{}%", percent),
+        }
+    }
+}
+
+impl FromStr for Matcher {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Matcher, String> {
+        let percent = |percent: &str| match percent.trim().strip_suffix('%') {
+            Some(percent) => match percent.trim().parse::<f64>() {
+                Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(percent),
+                _ => Err(format!("'{}' is not a percentage from 0 to 100", percent)),
+            },
+            None => Err(format!("'{}' is not a percentage, such as 1%", percent)),
+        };
+        if let Some(pattern) = s.strip_prefix("name:") {
+            return glob::Pattern::new(pattern)
+                .map(Matcher::Name)
+                .map_err(|e| e.to_string());
+        }
+        if let Some(rank) = s.strip_prefix("rank:") {
+            return match rank.trim().parse() {
+                Ok(rank) if rank > 0 => Ok(Matcher::Rank(rank)),
+                _ => Err(format!("'{}' is not a rank, such as 1", rank)),
+            };
+        }
+        if let Some(share) = s.strip_prefix("share<") {
+            return percent(share).map(Matcher::ShareBelow);
+        }
+        if let Some(share) = s.strip_prefix("share>") {
+            return percent(share).map(Matcher::ShareAbove);
+        }
+        Err(format!(
+            "'{}' is not a matcher: expected name:, rank:, share< or share>",
+            s
+        ))
+    }
+}
+
+/// Where a rule holds the threads it applies to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// The CPUs in the mask.
+    Cpus(usize),
+    /// The lowest CPU of each physical core, only within the given L3 domain if there is one.
+    Physical { l3: Option<u32> },
+    /// The CPUs of efficiency cores.
+    Efficiency,
+    /// The CPUs of performance cores, which is every CPU unless the processor is hybrid.
+    Performance,
+}
+
+impl Target {
+    /// Returns the target's CPUs as an affinity mask.
+    pub fn cpus(self, topology: &Topology) -> usize {
+        match self {
+            Target::Cpus(cpus) => cpus,
+            Target::Physical { l3 } => mask(
+                topology
+                    .cores()
+                    .iter()
+                    .map(|siblings| siblings[0])
+                    .filter(|&id| l3.is_none() || topology.cpu(id).map(|cpu| cpu.l3) == l3),
+            ),
+            Target::Efficiency => mask(
+                topology
+                    .cpus()
+                    .iter()
+                    .filter(|cpu| cpu.efficiency)
+                    .map(|cpu| cpu.id),
+            ),
+            Target::Performance => mask(
+                topology
+                    .cpus()
+                    .iter()
+                    .filter(|cpu| !cpu.efficiency)
+                    .map(|cpu| cpu.id),
+            ),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Target::Cpus(cpus) => write!(f, "{}", cpu_list(cpus)),
+            Target::Physical { l3: None } => write!(f, "physical"),
+            Target::Physical { l3: Some(l3) } => write!(f, "physical:l3={}", l3),
+            Target::Efficiency => write!(f, "efficiency"),
+            Target::Performance => write!(f, "performance"),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Target, String> {
+        match s {
+            "physical" => return Ok(Target::Physical { l3: None }),
+            "efficiency" => return Ok(Target::Efficiency),
+            "performance" => return Ok(Target::Performance),
+            _ => {}
+        }
+        if let Some(l3) = s.strip_prefix("physical:l3=") {
+            return l3.trim()
+                .parse()
+                .map(|l3| Target::Physical { l3: Some(l3) })
+                .map_err(|_| format!("'{}' is not an L3 domain", l3));
+        }
+        topology::parse_cpu_mask(s)
+            .map(Target::Cpus)
+            .map_err(|_| format!("'{}' is not a CPU list, physical, efficiency or performance", s))
+    }
+}
+
+/// Holds the threads a matcher selects on a target's CPUs.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub matcher: Matcher,
+    pub target: Target,
+}
+
+impl PartialEq for Rule {
+    fn eq(&self, other: &Rule) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} -> {}", self.matcher, self.target)
+    }
+}
+
+impl FromStr for Rule {
+    type Err = Error;
+
+    /// Parses `matcher -> target`, also accepting `→` as the arrow.
+    fn from_str(s: &str) -> HcbResult<Rule> {
+        let invalid = |reason: String| Error::Config(format!("Invalid rule '{}': {}", s, reason));
+        let (matcher, target) = match s.find("->").map(|at| (at, 2)).or_else(|| {
+            s.find('→').map(|at| (at, '→'.len_utf8()))
+        }) {
+            Some((at, len)) => (&s[..at], &s[at + len..]),
+            None => return Err(invalid("expected matcher -> target".to_owned())),
+        };
+        Ok(Rule {
+            matcher: matcher.trim().parse().map_err(&invalid)?,
+            target: target.trim().parse().map_err(&invalid)?,
+        })
+    }
+}
+
+/// Applies a profile's rules to a process each poll, remembering what it did so it can explain
+/// changes and undo them.
+#[derive(Debug, Clone, Default)]
+pub struct RuleEngine {
+    rules: Vec<Rule>,
+    /// The threads currently held by a rule.
+    held: HashSet<u32>,
+    /// The CPUs the held threads are held on.
+    cpus: usize,
+    /// The last explanation logged for each thread a rule matched.
+    explanations: HashMap<u32, String>,
+}
+
+impl RuleEngine {
+    pub fn new(rules: &[Rule]) -> RuleEngine {
+        RuleEngine {
+            rules: rules.to_vec(),
+            ..RuleEngine::default()
+        }
+    }
+
+    /// Fails if a rule has no CPUs among `allowed`, if two rules match the same threads but
+    /// hold them on different CPUs, if a rule matches a rank among the `hot_threads` most
+    /// active threads, or if a rule holds threads on CPUs `hot` for the hot threads or
+    /// `claimed` by other profiles.
+    pub fn check(
+        rules: &[Rule],
+        topology: &Topology,
+        allowed: usize,
+        hot_threads: usize,
+        hot: usize,
+        claimed: usize,
+    ) -> HcbResult<()> {
+        for (index, rule) in rules.iter().enumerate() {
+            let cpus = rule.target.cpus(topology) & allowed;
+            if cpus == 0 {
+                return Err(Error::Config(format!(
+                    "Rule '{}' has no CPUs the process may run on",
+                    rule
+                )));
+            }
+            if let Some(other) = rules[..index].iter().find(|other| {
+                other.matcher.to_string() == rule.matcher.to_string()
+                    && other.target.cpus(topology) & allowed != cpus
+            }) {
+                return Err(Error::Config(format!(
+                    "Rules '{}' and '{}' match the same threads but hold them on different CPUs",
+                    other, rule
+                )));
+            }
+            if let Matcher::Rank(rank) = rule.matcher {
+                if rank <= hot_threads {
+                    return Err(Error::Config(format!(
+                        "Rule '{}' matches one of the {} hot threads",
+                        rule, hot_threads
+                    )));
+                }
+            }
+            if cpus & hot != 0 {
+                return Err(Error::Config(format!(
+                    "Rule '{}' holds threads on CPUs {}, which the hot threads are assigned",
+                    rule,
+                    cpu_list(cpus & hot)
+                )));
+            }
+            if cpus & claimed != 0 {
+                return Err(Error::Config(format!(
+                    "Rule '{}' holds threads on CPUs {}, which other profiles have claimed",
+                    rule,
+                    cpu_list(cpus & claimed)
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the CPUs the rules list outright, which are the same on every machine.
+    pub fn listed(rules: &[Rule]) -> usize {
+        rules
+            .iter()
+            .filter_map(|rule| match rule.target {
+                Target::Cpus(cpus) => Some(cpus),
+                _ => None,
+            })
+            .fold(0, |listed, cpus| listed | cpus)
+    }
+
+    /// Returns the threads currently held by a rule.
+    pub fn held(&self) -> Vec<u32> {
+        let mut held: Vec<u32> = self.held.iter().cloned().collect();
+        held.sort_unstable();
+        held
+    }
+
+    /// Returns the CPUs the held threads are held on, as an affinity mask.
+    pub fn cpus(&self) -> usize {
+        self.cpus
+    }
+
+    /// Holds each thread of the process which matches a rule on that rule's CPUs among
+    /// `allowed`, leaving out the CPUs `reserved` for the hot threads, and gives threads which
+    /// stopped matching their original placement back.
+    ///
+    /// The threads `placed` as hot threads take the first ranks, so a rank rule never takes a
+    /// hot thread away while another thread is briefly busier.
+    ///
+    /// Returns the threads whose placement was given back. Each time the rule a thread matches,
+    /// or the outcome of applying it, changes, the reason is logged. Threads which exit before
+    /// they can be held or given back are ignored.
+    pub fn apply<P: OsProcess>(
+        &mut self,
+        process: &mut MonitoredProcess<P>,
+        topology: &Topology,
+        allowed: usize,
+        reserved: usize,
+        placed: &[u32],
+    ) -> Vec<u32> {
+        if self.rules.is_empty() {
+            return Vec::new();
+        }
+        let by_activity = process.thread_ids_by_activity();
+        let (mut ranked, rest): (Vec<u32>, Vec<u32>) =
+            by_activity.iter().partition(|id| placed.contains(id));
+        ranked.extend(rest);
+        let total: f64 = ranked
+            .iter()
+            .map(|id| process.threads()[id].smoothed())
+            .sum();
+        let mut held = HashSet::new();
+        let mut held_cpus = 0;
+        let mut explanations = HashMap::new();
+        for (index, &id) in ranked.iter().enumerate() {
+            let thread = process.threads_mut().get_mut(&id).unwrap();
+            let facts = Facts {
+                name: thread.name().unwrap_or(None),
+                rank: index + 1,
+                share: if total > 0.0 {
+                    Some(thread.smoothed() / total * 100.0)
+                } else {
+                    None
+                },
+            };
+            let matching: Vec<&Rule> = self.rules
+                .iter()
+                .filter(|rule| rule.matcher.matches(&facts))
+                .collect();
+            let rule = match matching.first() {
+                Some(rule) => rule,
+                None => continue,
+            };
+            let described = match facts.name {
+                Some(ref name) => format!("Thread {} ({})", id, name),
+                None => format!("Thread {}", id),
+            };
+            let cpus = rule.target.cpus(topology) & allowed & !reserved;
+            let mut explanation = if cpus == 0 {
+                format!(
+                    "{} matches rule '{}', but the hot threads or other profiles hold all of \
+                     its CPUs",
+                    described, rule
+                )
+            } else {
+                let result = match thread.affinity_mask() {
+                    Ok(mask) if mask == cpus => Ok(()),
+                    Ok(_) => thread.set_affinity_mask(cpus).map(|_| ()),
+                    Err(e) => Err(e),
+                };
+                if let Err(e) = result {
+                    debug!("Could not hold thread {}: {}", id, e);
+                    continue;
+                }
+                held.insert(id);
+                held_cpus |= cpus;
+                format!(
+                    "{} matches rule '{}', holding it on CPUs {}",
+                    described,
+                    rule,
+                    cpu_list(cpus)
+                )
+            };
+            for other in matching[1..].iter().filter(|other| other.target != rule.target) {
+                explanation.push_str(&format!(" instead of following rule '{}'", other));
+            }
+            if self.explanations.get(&id) != Some(&explanation) {
+                info!("{}.", explanation);
+            }
+            explanations.insert(id, explanation);
+        }
+        let mut released: Vec<u32> = self.held
+            .difference(&held)
+            .filter(|id| process.threads().contains_key(id))
+            .cloned()
+            .collect();
+        released.sort_unstable();
+        for &id in &released {
+            if !explanations.contains_key(&id) {
+                info!("Thread {} no longer matches a rule, restoring its placement.", id);
+            }
+            if let Err(e) = process.threads_mut().get_mut(&id).unwrap().restore() {
+                debug!("Could not restore thread {}: {}", id, e);
+            }
+        }
+        self.held = held;
+        self.cpus = held_cpus;
+        self.explanations = explanations;
+        released
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::Backend;
+    use mock::{Frame, MockBackend, MockProcess};
+    use std::time::{Duration, Instant};
+    use topology::Cpu;
+
+    /// Four cores with adjacently numbered siblings, the last two of them efficiency cores in a
+    /// second L3 domain.
+    fn hybrid() -> Topology {
+        Topology::new(
+            (0..8)
+                .map(|id| Cpu {
+                    id,
+                    core: id / 2,
+                    package: 0,
+                    l2: id / 2,
+                    l3: id / 4 * 4,
+                    node: 0,
+                    efficiency: id >= 4,
+                })
+                .collect(),
+        )
+    }
+
+    fn rule(rule: &str) -> Rule {
+        rule.parse().unwrap()
+    }
+
+    #[test]
+    fn round_trips_rules() {
+        for text in &[
+            "name:RenderThread* -> 4",
+            "rank:1 -> physical:l3=0",
+            "share<1% -> efficiency",
+            "share>12.5% -> performance",
+            "name:Audio? -> 0-3,6",
+            "rank:2 -> physical",
+        ] {
+            assert_eq!(rule(text).to_string(), *text);
+        }
+        assert_eq!(rule("rank:1→4").to_string(), "rank:1 -> 4");
+        for invalid in &["rank:0 -> 4", "rank:1", "share<1 -> 4", "cpu:1 -> 4", "rank:1 -> big"] {
+            assert!(invalid.parse::<Rule>().is_err(), "{} was accepted", invalid);
+        }
+    }
+
+    #[test]
+    fn resolves_targets_from_the_topology() {
+        let topology = hybrid();
+        let cpus = |target: &str| target.parse::<Target>().unwrap().cpus(&topology);
+        assert_eq!(cpus("4-5"), 0b11_0000);
+        assert_eq!(cpus("physical"), 0b0101_0101);
+        assert_eq!(cpus("physical:l3=4"), 0b0101_0000);
+        assert_eq!(cpus("efficiency"), 0b1111_0000);
+        assert_eq!(cpus("performance"), 0b1111);
+        assert_eq!(cpu_list(0b1011_1001), "0,3-5,7");
+    }
+
+    #[test]
+    fn rejects_conflicting_rules() {
+        let topology = hybrid();
+        let check = |rules: &[&str], hot, claimed| {
+            let rules: Vec<Rule> = rules.iter().map(|text| rule(text)).collect();
+            RuleEngine::check(&rules, &topology, 0xff, 2, hot, claimed)
+        };
+        assert!(check(&["rank:3 -> 4", "name:Audio* -> efficiency"], 0b11, 0b1000).is_ok());
+        // Repeating a rule changes nothing.
+        assert!(check(&["name:Audio* -> 4", "name:Audio* -> 4"], 0, 0).is_ok());
+        let conflicting: [(&[&str], usize, usize); 4] = [
+            (&["name:Audio* -> 4", "name:Audio* -> 5"], 0, 0),
+            (&["rank:2 -> 4"], 0, 0),
+            (&["share<1% -> efficiency"], 0b1_0000, 0),
+            (&["share<1% -> 6-7"], 0, 0b1000_0000),
+        ];
+        for &(rules, hot, claimed) in &conflicting {
+            assert!(check(rules, hot, claimed).is_err(), "{:?} was accepted", rules);
+        }
+        let rules = [rule("rank:3 -> efficiency")];
+        assert!(RuleEngine::check(&rules, &topology, 0b1111, 2, 0, 0).is_err());
+        let rules = [rule("name:x -> 4"), rule("share<1% -> physical"), rule("rank:1 -> 6-7")];
+        assert_eq!(RuleEngine::listed(&rules), 0b1101_0000);
+    }
+
+    #[test]
+    fn holds_matching_threads_and_releases_them() {
+        let backend = MockBackend::new();
+        let busy = Frame::from_activity(&[(1, 100), (2, 50), (3, 0)]);
+        let quiet = Frame::from_activity(&[(1, 100), (2, 0), (3, 0)]);
+        let frames = vec![Frame::default(), busy.clone(), busy, quiet];
+        backend.add_process(1000, "game", 0xff, frames);
+        backend.set_thread_name(1000, 2, "RenderThread 0");
+        let process = backend.processes().unwrap().remove(0);
+        let start = Instant::now();
+        let mut process = MonitoredProcess::new(process, start).unwrap();
+        let rules = [
+            rule("name:RenderThread* -> 6"),
+            rule("share>40% -> 4"),
+            rule("share<1% -> efficiency"),
+        ];
+        let mut engine = RuleEngine::new(&rules);
+        for secs in 1..3 {
+            backend.tick();
+            process.update_at(start + Duration::from_secs(secs)).unwrap();
+        }
+        // Thread 1 is matched by a rule whose CPUs the hot threads hold.
+        let released = engine.apply(&mut process, &hybrid(), 0xff, 0b1_0000, &[]);
+        assert_eq!(released, vec![]);
+        assert_eq!(engine.held(), vec![2, 3]);
+        assert_eq!(engine.cpus(), 0b1110_0000);
+        let affinity = |process: &MonitoredProcess<MockProcess>, id| {
+            process.threads()[&id].thread().affinity_mask().unwrap()
+        };
+        assert_eq!(affinity(&process, 2), 0b100_0000);
+        assert_eq!(affinity(&process, 3), 0b1110_0000);
+        // Thread 2 goes quiet and is held with the efficiency cores from then on.
+        backend.tick();
+        process.update_at(start + Duration::from_secs(3)).unwrap();
+        engine.apply(&mut process, &hybrid(), 0xff, 0, &[]);
+        assert_eq!(engine.held(), vec![1, 2, 3]);
+        assert_eq!(affinity(&process, 1), 0b1_0000);
+        assert_eq!(affinity(&process, 2), 0b100_0000);
+        // Without rules matching them, threads are given their placement back.
+        let mut engine = RuleEngine {
+            rules: vec![rule("rank:9 -> 0")],
+            ..engine
+        };
+        let released = engine.apply(&mut process, &hybrid(), 0xff, 0, &[]);
+        assert_eq!(released, vec![1, 2, 3]);
+        assert_eq!(affinity(&process, 1), 0xff);
+    }
+
+    #[test]
+    fn ranks_the_placed_hot_threads_first() {
+        let backend = MockBackend::new();
+        let busy = Frame::from_activity(&[(1, 100), (2, 80), (3, 90)]);
+        backend.add_process(1000, "game", 0xff, vec![Frame::default(), busy.clone(), busy]);
+        let process = backend.processes().unwrap().remove(0);
+        let start = Instant::now();
+        let mut process = MonitoredProcess::new(process, start).unwrap();
+        for secs in 1..3 {
+            backend.tick();
+            process.update_at(start + Duration::from_secs(secs)).unwrap();
+        }
+        // Thread 3 is busier than thread 2 for now, but thread 2 keeps its place.
+        let mut engine = RuleEngine::new(&[rule("rank:3 -> 6")]);
+        engine.apply(&mut process, &hybrid(), 0xff, 0b1010, &[1, 2]);
+        assert_eq!(engine.held(), vec![3]);
+    }
+
+    #[test]
+    fn skips_threads_which_exit() {
+        let backend = MockBackend::new();
+        let busy = Frame::from_activity(&[(1, 100), (2, 50)]);
+        let frames = vec![
+            Frame::default(),
+            busy.clone(),
+            busy,
+            Frame::from_activity(&[(1, 100)]),
+        ];
+        backend.add_process(1000, "game", 0xff, frames);
+        let process = backend.processes().unwrap().remove(0);
+        let start = Instant::now();
+        let mut process = MonitoredProcess::new(process, start).unwrap();
+        for secs in 1..3 {
+            backend.tick();
+            process.update_at(start + Duration::from_secs(secs)).unwrap();
+        }
+        // Thread 2 exits after the threads were sampled but before it can be held.
+        backend.tick();
+        let mut engine = RuleEngine::new(&[rule("share>0% -> 4")]);
+        assert_eq!(engine.apply(&mut process, &hybrid(), 0xff, 0, &[]), vec![]);
+        assert_eq!(engine.held(), vec![1]);
+        assert_eq!(engine.cpus(), 0b1_0000);
+    }
+}
@@ -0,0 +1,377 @@
+//! Choosing which process to manage.
+
+use std::fmt;
+use std::str::FromStr;
+
+use glob;
+use regex::Regex;
+
+use backend::OsProcess;
+use {Error, HcbResult};
+
+/// The property of a process a selector's pattern is matched against.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Field {
+    /// The unqualified name of the executable.
+    Name,
+    /// The full path of the executable.
+    Path,
+    /// The command line, with arguments separated by spaces.
+    CommandLine,
+}
+
+impl Field {
+    fn prefix(self) -> &'static str {
+        match self {
+            Field::Name => "name",
+            Field::Path => "path",
+            Field::CommandLine => "cmdline",
+        }
+    }
+
+    fn read<P: OsProcess>(self, process: &P) -> HcbResult<String> {
+        match self {
+            Field::Name => process.name(),
+            Field::Path => Ok(process.path()?.to_string_lossy().into_owned()),
+            Field::CommandLine => process.command_line(),
+        }
+    }
+}
+
+/// How a selector's pattern is matched.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// The whole value must equal the string.
+    Exact(String),
+    /// The whole value must match the shell-style wildcard pattern.
+    Glob(glob::Pattern),
+    /// Some part of the value must match the regular expression.
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn matches(&self, value: &str) -> bool {
+        match *self {
+            Pattern::Exact(ref exact) => value == exact,
+            Pattern::Glob(ref glob) => glob.matches(value),
+            Pattern::Regex(ref regex) => regex.is_match(value),
+        }
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Pattern::Exact(ref exact) => write!(f, "{}", exact),
+            Pattern::Glob(ref glob) => write!(f, "glob:{}", glob),
+            Pattern::Regex(ref regex) => write!(f, "regex:{}", regex),
+        }
+    }
+}
+
+/// Returns true if the process was started by Steam for the app with the given id.
+///
+/// Steam sets `SteamAppId` and `SteamGameId` for everything it launches, including its runtime's
+/// and Wine's helper processes, so this alone does not single out the game.
+fn is_steam_app<P: OsProcess>(process: &P, app: u32) -> HcbResult<bool> {
+    let app = app.to_string();
+    let mut found = false;
+    for name in &["SteamAppId", "SteamGameId"] {
+        found |= process.environment_variable(name)?.as_ref() == Some(&app);
+    }
+    Ok(found)
+}
+
+/// Names of the processes Steam's runtime and Wine start alongside a game.
+const STEAM_HELPERS: &[&str] = &[
+    "bash",
+    "explorer.exe",
+    "plugplay.exe",
+    "pv-adverb",
+    "python3",
+    "reaper",
+    "rpcss.exe",
+    "services.exe",
+    "sh",
+    "srt-bwrap",
+    "start.exe",
+    "steam",
+    "steam.exe",
+    "steam-runtime-launcher-service",
+    "steamerrorreporter.exe",
+    "svchost.exe",
+    "tabtip.exe",
+    "wine",
+    "wine64",
+    "wineboot.exe",
+    "winedevice.exe",
+    "wineserver",
+];
+
+/// Returns true if the process is one of Steam's runtime or Wine helpers rather than a game.
+fn is_steam_helper<P: OsProcess>(process: &P) -> HcbResult<bool> {
+    let name = process.name()?;
+    Ok(STEAM_HELPERS.contains(&name.as_str())
+        || name.starts_with("pressure-vessel-")
+        || name.ends_with("-preloader"))
+}
+
+/// Identifies the process to manage.
+#[derive(Debug, Clone)]
+pub enum Selector {
+    /// The process with this id.
+    Pid(u32),
+    /// The process Steam launched for this app id, such as 252950 for Rocket League, which also
+    /// matches `within`, or else is not one of Steam's runtime or Wine helpers.
+    SteamApp {
+        app: u32,
+        within: Option<Box<Selector>>,
+    },
+    /// Processes whose field matches the pattern.
+    Match { field: Field, pattern: Pattern },
+}
+
+impl Selector {
+    /// Selects processes whose executable is named exactly `name`.
+    pub fn name(name: &str) -> Selector {
+        Selector::Match {
+            field: Field::Name,
+            pattern: Pattern::Exact(name.to_owned()),
+        }
+    }
+
+    /// Returns true if the process is selected.
+    ///
+    /// Processes whose name, path, command line or environment cannot be read are not selected.
+    pub fn matches<P: OsProcess>(&self, process: &P) -> bool {
+        match *self {
+            Selector::Pid(id) => process.id() == id,
+            Selector::SteamApp { app, ref within } => {
+                let game = match *within {
+                    Some(ref within) => within.matches(process),
+                    None => !is_steam_helper(process).unwrap_or(true),
+                };
+                game && is_steam_app(process, app).unwrap_or(false)
+            }
+            Selector::Match { field, ref pattern } => field
+                .read(process)
+                .map(|value| pattern.matches(&value))
+                .unwrap_or(false),
+        }
+    }
+}
+
+impl Default for Selector {
+    /// Selects Rocket League.
+    fn default() -> Selector {
+        Selector::name("RocketLeague.exe")
+    }
+}
+
+impl PartialEq for Selector {
+    fn eq(&self, other: &Selector) -> bool {
+        self.to_string() == other.to_string()
+    }
+}
+
+impl FromStr for Selector {
+    type Err = Error;
+
+    /// Parses `[field:][kind:]pattern`, where field is `name` (the default), `path` or `cmdline`
+    /// and kind is `exact` (the default), `glob` or `regex`, or `pid:id`, or
+    /// `steam:appid[:[field:][kind:]pattern]`.
+    ///
+    /// Steam app ids are read from the process's environment, which only the Linux backend can
+    /// do, so they are rejected elsewhere.
+    fn from_str(s: &str) -> HcbResult<Selector> {
+        let invalid = |reason: String| Error::Config(format!("Invalid target '{}': {}", s, reason));
+        if let Some(id) = s.strip_prefix("pid:") {
+            return id.trim()
+                .parse()
+                .map(Selector::Pid)
+                .map_err(|_| invalid(format!("'{}' is not a process id", id)));
+        }
+        if let Some(rest) = s.strip_prefix("steam:") {
+            if !cfg!(target_os = "linux") {
+                return Err(invalid("Steam app ids can only be read on Linux".to_owned()));
+            }
+            let (app, within) = match rest.find(':') {
+                Some(end) => (&rest[..end], Some(&rest[end + 1..])),
+                None => (rest, None),
+            };
+            let app = app.trim()
+                .parse()
+                .map_err(|_| invalid(format!("'{}' is not a Steam app id", app)))?;
+            // Without a pattern, the game is told apart from the helpers Steam's runtime and
+            // Wine start with the same app id by their names.
+            let within = match within.map(str::parse).transpose()? {
+                Some(within @ Selector::Match { .. }) => Some(Box::new(within)),
+                Some(_) => {
+                    return Err(invalid("the game must be selected by a pattern".to_owned()))
+                }
+                None => None,
+            };
+            return Ok(Selector::SteamApp { app, within });
+        }
+        let (field, rest) = match s.find(':').map(|end| (&s[..end], &s[end + 1..])) {
+            Some(("name", rest)) => (Field::Name, rest),
+            Some(("path", rest)) => (Field::Path, rest),
+            Some(("cmdline", rest)) => (Field::CommandLine, rest),
+            _ => (Field::Name, s),
+        };
+        let pattern = match rest.find(':').map(|end| (&rest[..end], &rest[end + 1..])) {
+            Some(("exact", exact)) => Pattern::Exact(exact.to_owned()),
+            Some(("glob", glob)) => Pattern::Glob(
+                glob::Pattern::new(glob).map_err(|e| invalid(e.to_string()))?,
+            ),
+            Some(("regex", regex)) => {
+                Pattern::Regex(Regex::new(regex).map_err(|e| invalid(e.to_string()))?)
+            }
+            _ => Pattern::Exact(rest.to_owned()),
+        };
+        if pattern.to_string().is_empty() {
+            return Err(invalid("the pattern is empty".to_owned()));
+        }
+        Ok(Selector::Match { field, pattern })
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Selector::Pid(id) => write!(f, "pid:{}", id),
+            Selector::SteamApp {
+                app,
+                within: Some(ref within),
+            } => write!(f, "steam:{}:{}", app, within),
+            Selector::SteamApp { app, within: None } => write!(f, "steam:{}", app),
+            Selector::Match {
+                field: Field::Name,
+                pattern: Pattern::Exact(ref exact),
+            } if !exact.contains(':') => write!(f, "{}", exact),
+            Selector::Match {
+                field,
+                pattern: Pattern::Exact(ref exact),
+            } => write!(f, "{}:exact:{}", field.prefix(), exact),
+            Selector::Match { field, ref pattern } => write!(f, "{}:{}", field.prefix(), pattern),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use backend::Backend;
+    use mock::{Frame, MockBackend, MockProcess};
+
+    fn process() -> MockProcess {
+        let backend = MockBackend::new();
+        backend.add_process(42, "RocketLeague.exe", 0xff, vec![Frame::default()]);
+        backend.set_command_line(
+            42,
+            r"C:\Games\rocketleague\Binaries\Win64\RocketLeague.exe",
+            r"C:\Games\rocketleague\Binaries\Win64\RocketLeague.exe -nomovie",
+        );
+        backend.processes().unwrap().remove(0)
+    }
+
+    fn selects(selector: &str) -> bool {
+        selector.parse::<Selector>().unwrap().matches(&process())
+    }
+
+    #[test]
+    fn matches_each_field() {
+        assert!(selects("RocketLeague.exe"));
+        assert!(!selects("rocketleague.exe"));
+        assert!(selects("glob:Rocket*.exe"));
+        assert!(selects("name:regex:(?i)^rocket"));
+        assert!(selects(r"path:glob:C:\Games\*\RocketLeague.exe"));
+        assert!(selects("cmdline:regex:-nomovie"));
+        assert!(!selects("cmdline:exact:RocketLeague.exe"));
+        assert!(selects("pid:42"));
+        assert!(!selects("pid:43"));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn matches_steam_games_by_app_id_and_name() {
+        let backend = MockBackend::new();
+        backend.add_process(10, "pv-adverb", 0xff, vec![]);
+        backend.add_process(11, "steam.exe", 0xff, vec![]);
+        backend.add_process(12, "RocketLeague.exe", 0xff, vec![]);
+        backend.add_process(13, "RocketLeague.exe", 0xff, vec![]);
+        for id in 10..13 {
+            backend.set_environment_variable(id, "SteamAppId", "252950");
+        }
+        let selector: Selector = "steam:252950:RocketLeague.exe".parse().unwrap();
+        let selected: Vec<u32> = backend
+            .processes()
+            .unwrap()
+            .iter()
+            .filter(|p| selector.matches(*p))
+            .map(|p| p.id())
+            .collect();
+        assert_eq!(selected, vec![12]);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn picks_the_steam_game_out_of_the_helpers_by_app_id_alone() {
+        let backend = MockBackend::new();
+        backend.add_process(10, "pv-adverb", 0xff, vec![]);
+        backend.add_process(11, "steam.exe", 0xff, vec![]);
+        backend.add_process(12, "RocketLeague.exe", 0xff, vec![]);
+        backend.add_process(13, "wineserver", 0xff, vec![]);
+        backend.add_process(14, "wine64-preloader", 0xff, vec![]);
+        backend.add_process(15, "RocketLeague.exe", 0xff, vec![]);
+        for id in 10..15 {
+            backend.set_environment_variable(id, "SteamAppId", "252950");
+        }
+        let selector: Selector = "steam:252950".parse().unwrap();
+        let selected: Vec<u32> = backend
+            .processes()
+            .unwrap()
+            .iter()
+            .filter(|p| selector.matches(*p))
+            .map(|p| p.id())
+            .collect();
+        assert_eq!(selected, vec![12]);
+    }
+
+    #[test]
+    fn round_trips_selectors() {
+        for selector in &[
+            "RocketLeague.exe",
+            "name:glob:Rocket*",
+            "path:exact:/usr/bin/game",
+            "cmdline:regex:--level \\d+",
+            "pid:7",
+        ] {
+            assert_eq!(selector.parse::<Selector>().unwrap().to_string(), *selector);
+        }
+        assert_eq!(Selector::default().to_string(), "RocketLeague.exe");
+    }
+
+    #[test]
+    fn rejects_invalid_selectors() {
+        assert!("pid:abc".parse::<Selector>().is_err());
+        assert!("regex:(".parse::<Selector>().is_err());
+        assert!("name:".parse::<Selector>().is_err());
+        assert!("steam:rocketleague".parse::<Selector>().is_err());
+        assert!("steam:252950:".parse::<Selector>().is_err());
+        assert!("steam:252950:pid:7".parse::<Selector>().is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn round_trips_steam_selectors() {
+        for selector in &[
+            "steam:252950",
+            "steam:252950:RocketLeague.exe",
+            "steam:252950:name:glob:Rocket*",
+        ] {
+            assert_eq!(selector.parse::<Selector>().unwrap().to_string(), *selector);
+        }
+        assert!("steam:rocketleague:RocketLeague.exe".parse::<Selector>().is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use placement::Mode;
+use policy::Policy;
+use procext::Smoothing;
+use rules::Rule;
+use selector::Selector;
+use topology;
+use {Error, HcbResult};
+
+/// Tunables for the thread manager, which together make up one profile.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    /// Identifies the profile in logs and when sharing CPUs with other profiles.
+    pub name: String,
+    /// Which process to manage.
+    pub target: Selector,
+    /// How often thread activity is sampled.
+    pub poll_interval: Duration,
+    /// How long the hot threads must stay the same before they are assigned.
+    pub settling_period: Duration,
+    /// How many of the most active threads to manage.
+    pub hot_threads: usize,
+    /// How thread activity is smoothed before the hot threads are picked.
+    pub smoothing: Smoothing,
+    /// The CPUs the hot threads may be assigned to, as an affinity mask.
+    pub cores: usize,
+    /// How the hot threads' CPUs are chosen.
+    pub policy: Policy,
+    /// How the hot threads are held on their CPUs.
+    pub mode: Mode,
+    /// Whether to keep the process's other threads off the hot threads' CPUs.
+    pub evict: bool,
+    /// Whether to keep the target's owner's other processes off the hot threads' CPUs while the
+    /// target runs.
+    pub reserve_system: bool,
+    /// Whether the threads of the target's descendant processes compete with its own to be
+    /// the hot threads.
+    pub descendants: bool,
+    /// Rules holding particular threads on particular CPUs, in order of precedence.
+    pub rules: Vec<Rule>,
+}
+
+/// Settings which add to a list each time they are set, rather than replacing it.
+pub const LIST_KEYS: &[&str] = &["rule"];
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            name: "default".to_owned(),
+            target: Selector::default(),
+            poll_interval: Duration::from_secs(1),
+            settling_period: Duration::from_secs(15),
+            hot_threads: 3,
+            smoothing: Smoothing::default(),
+            cores: !0,
+            policy: Policy::default(),
+            mode: Mode::default(),
+            evict: false,
+            reserve_system: false,
+            descendants: false,
+            rules: Vec::new(),
+        }
+    }
+}
+
+fn parse<T: FromStr>(key: &str, value: &str) -> HcbResult<T> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| Error::Config(format!("Invalid value '{}' for {}", value, key)))
+}
+
+/// Parses a count or number of seconds which must not be 0, such as the poll interval, which
+/// would otherwise spin.
+fn positive<T: FromStr + Default + PartialEq>(key: &str, value: &str) -> HcbResult<T> {
+    let parsed = parse(key, value)?;
+    if parsed == T::default() {
+        return Err(Error::Config(format!(
+            "Invalid value '{}' for {}, which must be at least 1",
+            value, key
+        )));
+    }
+    Ok(parsed)
+}
+
+impl Settings {
+    /// Sets the setting named `key` from its textual value, using the same names and formats as
+    /// the command line options.
+    pub fn set(&mut self, key: &str, value: &str) -> HcbResult<()> {
+        match key {
+            "name" => self.name = value.to_owned(),
+            "target" => self.target = value.parse()?,
+            "poll" => self.poll_interval = Duration::from_secs(positive(key, value)?),
+            "settle" => self.settling_period = Duration::from_secs(parse(key, value)?),
+            "threads" => self.hot_threads = positive(key, value)?,
+            "smoothing" => {
+                self.smoothing = Smoothing::new(parse(key, value)?, self.smoothing.hysteresis)?
+            }
+            "hysteresis" => {
+                self.smoothing = Smoothing::new(self.smoothing.alpha, parse(key, value)?)?
+            }
+            "cores" => self.cores = topology::parse_cpu_mask(value)?,
+            "policy" => self.policy = value.parse()?,
+            "mode" => self.mode = value.parse()?,
+            "evict" => self.evict = parse(key, value)?,
+            "reserve-system" => self.reserve_system = parse(key, value)?,
+            "descendants" => self.descendants = parse(key, value)?,
+            "rule" => self.rules.push(value.parse()?),
+            _ => return Err(Error::Config(format!("Unknown setting '{}'", key))),
+        }
+        Ok(())
+    }
+
+    /// Applies a profile written as `key=value` pairs separated by semicolons, such as
+    /// `name=encoder;target=obs64.exe;threads=2;cores=8-11`.
+    pub fn apply_profile(&mut self, profile: &str) -> HcbResult<()> {
+        for pair in profile.split(';').filter(|pair| !pair.trim().is_empty()) {
+            match pair.find('=') {
+                Some(eq) => self.set(pair[..eq].trim(), &pair[eq + 1..])?,
+                None => return Err(Error::Config(format!("Expected key=value, found '{}'", pair))),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn applies_profiles() {
+        let mut settings = Settings::default();
+        settings
+            .apply_profile("name=encoder; target=obs64.exe;threads=2;cores=8-11;policy=1,3")
+            .unwrap();
+        settings
+            .apply_profile("rule=name:x264_* -> 8;rule=share<1% -> efficiency")
+            .unwrap();
+        assert_eq!(settings.name, "encoder");
+        assert_eq!(settings.target, Selector::name("obs64.exe"));
+        assert_eq!(settings.hot_threads, 2);
+        assert_eq!(settings.cores, 0xf00);
+        assert_eq!(settings.policy.to_string(), "1,3");
+        let rules: Vec<String> = settings.rules.iter().map(Rule::to_string).collect();
+        assert_eq!(rules, vec!["name:x264_* -> 8", "share<1% -> efficiency"]);
+        assert!(settings.clone().apply_profile("threads=two").is_err());
+        assert!(settings.clone().apply_profile("colour=blue").is_err());
+        assert!(settings.clone().apply_profile("threads").is_err());
+        assert!(settings.clone().apply_profile("threads=0").is_err());
+        assert!(settings.clone().apply_profile("poll=0").is_err());
+        assert!(settings.clone().apply_profile("settle=0").is_ok());
+    }
+}
@@ -0,0 +1,112 @@
+//! A model of how logical CPUs map onto cores, caches and packages.
+
+use {Error, HcbResult};
+
+/// Parses a CPU list such as `0-3,8,10-11`, in the format the Linux kernel uses.
+pub fn parse_cpu_list(list: &str) -> Option<Vec<u32>> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let mut bounds = range.splitn(2, '-').map(|bound| bound.trim().parse::<u32>());
+        match (bounds.next(), bounds.next()) {
+            (Some(Ok(first)), None) => cpus.push(first),
+            (Some(Ok(first)), Some(Ok(last))) => cpus.extend(first..=last),
+            _ => return None,
+        }
+    }
+    Some(cpus)
+}
+
+/// Parses a CPU list into an affinity mask.
+pub fn parse_cpu_mask(list: &str) -> HcbResult<usize> {
+    let invalid = || Error::Config(format!("Invalid CPU list '{}'", list));
+    let mut mask = 0;
+    for cpu in parse_cpu_list(list).ok_or_else(invalid)? {
+        if cpu >= usize::BITS {
+            return Err(invalid());
+        }
+        mask |= 1 << cpu;
+    }
+    if mask == 0 {
+        return Err(invalid());
+    }
+    Ok(mask)
+}
+
+/// A logical CPU and the hardware it shares with others.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cpu {
+    /// The logical CPU number used in affinity masks.
+    pub id: u32,
+    /// The physical core the CPU belongs to, unique across packages.
+    pub core: u32,
+    /// The physical package (socket) the CPU belongs to.
+    pub package: u32,
+    /// The L2 cache domain the CPU belongs to.
+    pub l2: u32,
+    /// The L3 cache domain the CPU belongs to.
+    pub l3: u32,
+    /// The NUMA node the CPU belongs to.
+    pub node: u32,
+    /// Whether the CPU belongs to a slower efficiency core of a hybrid processor.
+    pub efficiency: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topology {
+    cpus: Vec<Cpu>,
+}
+
+impl Topology {
+    pub fn new(mut cpus: Vec<Cpu>) -> Topology {
+        cpus.sort_by_key(|cpu| cpu.id);
+        Topology { cpus }
+    }
+
+    /// Assumes `logical` CPUs in a single package, with `threads_per_core` adjacently numbered
+    /// SMT siblings per core, a private L2 per core and one shared L3.
+    pub fn uniform(logical: u32, threads_per_core: u32) -> Topology {
+        let threads_per_core = threads_per_core.max(1);
+        Topology::new(
+            (0..logical)
+                .map(|id| Cpu {
+                    id,
+                    core: id / threads_per_core,
+                    package: 0,
+                    l2: id / threads_per_core,
+                    l3: 0,
+                    node: 0,
+                    efficiency: false,
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns every logical CPU, ordered by id.
+    pub fn cpus(&self) -> &[Cpu] {
+        &self.cpus
+    }
+
+    pub fn cpu(&self, id: u32) -> Option<&Cpu> {
+        self.cpus.iter().find(|cpu| cpu.id == id)
+    }
+
+    /// Returns the logical CPUs of each physical core, ordered by the cores' lowest CPU.
+    pub fn cores(&self) -> Vec<Vec<u32>> {
+        let mut cores: Vec<(u32, Vec<u32>)> = Vec::new();
+        for cpu in &self.cpus {
+            match cores.iter_mut().find(|&&mut (core, _)| core == cpu.core) {
+                Some(&mut (_, ref mut siblings)) => siblings.push(cpu.id),
+                None => cores.push((cpu.core, vec![cpu.id])),
+            }
+        }
+        cores.into_iter().map(|(_, siblings)| siblings).collect()
+    }
+
+    /// Returns the logical CPUs sharing a physical core with `id`, including itself.
+    pub fn siblings(&self, id: u32) -> Vec<u32> {
+        self.cores()
+            .into_iter()
+            .find(|siblings| siblings.contains(&id))
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,180 @@
+//! Noticing processes starting and exiting.
+
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+use backend::{Backend, OsProcess};
+use clock::Clock;
+use HcbResult;
+
+/// A change to the set of running processes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ProcessEvent {
+    /// The process with this id started, or executed a new program.
+    Started(u32),
+    /// The process with this id exited.
+    Exited(u32),
+    /// Events were lost, so the running processes should be enumerated again.
+    Overflow,
+}
+
+/// A source of process start and exit events.
+pub trait ProcessWatcher {
+    /// Waits up to `timeout` for processes to start or exit, returning as soon as any have.
+    ///
+    /// An empty list means the timeout passed without any events.
+    fn wait(&mut self, timeout: Duration) -> HcbResult<Vec<ProcessEvent>>;
+
+    /// Tells the watcher whether events are needed, so that a watcher which has to enumerate
+    /// every process to find them can skip that while they are not.
+    ///
+    /// Events are needed until told otherwise. Once they are needed again, a watcher may report
+    /// what changed in the meantime or nothing at all.
+    fn set_needed(&mut self, _needed: bool) {}
+}
+
+impl<W: ProcessWatcher + ?Sized> ProcessWatcher for Box<W> {
+    fn wait(&mut self, timeout: Duration) -> HcbResult<Vec<ProcessEvent>> {
+        (**self).wait(timeout)
+    }
+
+    fn set_needed(&mut self, needed: bool) {
+        (**self).set_needed(needed)
+    }
+}
+
+/// Updates `known` to `current`, returning how it changed.
+pub fn diff_ids(known: &mut HashSet<u32>, current: HashSet<u32>) -> Vec<ProcessEvent> {
+    let mut started: Vec<u32> = current.difference(known).cloned().collect();
+    let mut exited: Vec<u32> = known.difference(&current).cloned().collect();
+    started.sort_unstable();
+    exited.sort_unstable();
+    *known = current;
+    started
+        .into_iter()
+        .map(ProcessEvent::Started)
+        .chain(exited.into_iter().map(ProcessEvent::Exited))
+        .collect()
+}
+
+/// Updates `known` to `current`, which map process ids to what identifies the program each
+/// runs, returning how they changed.
+///
+/// A process whose identity changed, because it executed a new program or its id was reused,
+/// is reported as started again.
+pub fn diff_identities<T: PartialEq>(
+    known: &mut HashMap<u32, T>,
+    current: HashMap<u32, T>,
+) -> Vec<ProcessEvent> {
+    let mut started: Vec<u32> = current
+        .iter()
+        .filter(|&(id, identity)| known.get(id) != Some(identity))
+        .map(|(&id, _)| id)
+        .collect();
+    let mut exited: Vec<u32> = known
+        .keys()
+        .filter(|id| !current.contains_key(id))
+        .cloned()
+        .collect();
+    started.sort_unstable();
+    exited.sort_unstable();
+    *known = current;
+    started
+        .into_iter()
+        .map(ProcessEvent::Started)
+        .chain(exited.into_iter().map(ProcessEvent::Exited))
+        .collect()
+}
+
+/// Finds process events by enumerating every process through the backend once per wait, unless
+/// no events are needed.
+///
+/// This works with any backend, but always waits for the whole timeout.
+#[derive(Debug)]
+pub struct PollingWatcher<'a, B: 'a, C: 'a> {
+    backend: &'a B,
+    clock: &'a C,
+    known: HashSet<u32>,
+    needed: bool,
+}
+
+impl<'a, B: Backend, C: Clock> PollingWatcher<'a, B, C> {
+    pub fn new(backend: &'a B, clock: &'a C) -> HcbResult<PollingWatcher<'a, B, C>> {
+        let known = PollingWatcher::<B, C>::ids(backend)?;
+        Ok(PollingWatcher {
+            backend,
+            clock,
+            known,
+            needed: true,
+        })
+    }
+
+    fn ids(backend: &B) -> HcbResult<HashSet<u32>> {
+        Ok(backend.processes()?.iter().map(|p| p.id()).collect())
+    }
+}
+
+impl<'a, B: Backend, C: Clock> ProcessWatcher for PollingWatcher<'a, B, C> {
+    fn wait(&mut self, timeout: Duration) -> HcbResult<Vec<ProcessEvent>> {
+        self.clock.sleep(timeout);
+        if !self.needed {
+            return Ok(Vec::new());
+        }
+        let current = PollingWatcher::<B, C>::ids(self.backend)?;
+        Ok(diff_ids(&mut self.known, current))
+    }
+
+    fn set_needed(&mut self, needed: bool) {
+        self.needed = needed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clock::ManualClock;
+    use mock::{Frame, MockBackend};
+
+    #[test]
+    fn polling_reports_started_and_exited_processes() {
+        let backend = MockBackend::new();
+        let clock = ManualClock::new();
+        backend.add_process(1, "init", 0xff, vec![Frame::default(); 2]);
+        backend.add_process(2, "game", 0xff, vec![]);
+        let mut watcher = PollingWatcher::new(&backend, &clock).unwrap();
+        assert_eq!(watcher.wait(Duration::from_secs(1)).unwrap(), vec![]);
+        assert_eq!(clock.elapsed(), Duration::from_secs(1));
+        // The game exits as the encoder starts.
+        backend.tick();
+        backend.add_process(3, "encoder", 0xff, vec![Frame::default()]);
+        assert_eq!(
+            watcher.wait(Duration::from_secs(1)).unwrap(),
+            vec![ProcessEvent::Started(3), ProcessEvent::Exited(2)]
+        );
+        watcher.set_needed(false);
+        backend.add_process(4, "obs64.exe", 0xff, vec![]);
+        assert_eq!(watcher.wait(Duration::from_secs(1)).unwrap(), vec![]);
+        watcher.set_needed(true);
+        assert_eq!(
+            watcher.wait(Duration::from_secs(1)).unwrap(),
+            vec![ProcessEvent::Started(4)]
+        );
+    }
+
+    #[test]
+    fn reports_processes_whose_identity_changed_as_started() {
+        let mut known: HashMap<u32, &str> = [(1, "init"), (2, "wine64-preloader"), (3, "sh")]
+            .iter()
+            .cloned()
+            .collect();
+        let current = [(1, "init"), (2, "RocketLeague.ex"), (4, "sh")]
+            .iter()
+            .cloned()
+            .collect();
+        assert_eq!(
+            diff_identities(&mut known, current),
+            vec![ProcessEvent::Started(2), ProcessEvent::Started(4), ProcessEvent::Exited(3)]
+        );
+        assert_eq!(known[&2], "RocketLeague.ex");
+    }
+}
@@ -0,0 +1,100 @@
+use std::path::PathBuf;
+
+use backend::{Backend, OsProcess, OsThread};
+use win::{Process, Thread};
+use HcbResult;
+
+/// The Windows process and thread backend.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct WinBackend;
+
+impl Backend for WinBackend {
+    type Process = Process;
+
+    fn processes(&self) -> HcbResult<Vec<Process>> {
+        Ok(Process::all()?.collect())
+    }
+
+    fn process(&self, id: u32) -> HcbResult<Process> {
+        Ok(Process::from_id(id)?)
+    }
+}
+
+impl OsProcess for Process {
+    type Thread = Thread;
+
+    fn id(&self) -> u32 {
+        Process::id(self)
+    }
+
+    fn running(&self) -> bool {
+        Process::running(self)
+    }
+
+    fn name(&self) -> HcbResult<String> {
+        Ok(Process::name(self)?)
+    }
+
+    fn path(&self) -> HcbResult<PathBuf> {
+        Ok(Process::path(self)?)
+    }
+
+    fn command_line(&self) -> HcbResult<String> {
+        Ok(Process::command_line(self)?)
+    }
+
+    fn parent_id(&self) -> HcbResult<u32> {
+        Ok(Process::parent_id(self)?)
+    }
+
+    fn start_time(&self) -> HcbResult<u64> {
+        Ok(Process::start_time(self)?)
+    }
+
+    /// The login session, or `None` for services and the system, which run in session 0.
+    fn owner(&self) -> HcbResult<Option<u32>> {
+        Ok(Some(Process::session_id(self)?).filter(|&session| session != 0))
+    }
+
+    fn affinity_mask(&self) -> HcbResult<usize> {
+        Ok(Process::affinity_mask(self)?)
+    }
+
+    fn set_affinity_mask(&mut self, mask: usize) -> HcbResult<usize> {
+        Ok(Process::set_affinity_mask(self, mask)?)
+    }
+
+    fn thread_ids(&self) -> HcbResult<Vec<u32>> {
+        Ok(Process::thread_ids(self)?.collect())
+    }
+
+    fn thread(&self, id: u32) -> HcbResult<Thread> {
+        Ok(Thread::from_id(id)?)
+    }
+}
+
+impl OsThread for Thread {
+    fn id(&self) -> u32 {
+        Thread::id(self)
+    }
+
+    fn cpu_counter(&self) -> HcbResult<u64> {
+        Ok(self.cycle_time()?)
+    }
+
+    fn ideal_processor(&self) -> HcbResult<u32> {
+        Ok(Thread::ideal_processor(self)?)
+    }
+
+    fn set_ideal_processor(&mut self, processor: u32) -> HcbResult<u32> {
+        Ok(Thread::set_ideal_processor(self, processor)?)
+    }
+
+    fn affinity_mask(&self) -> HcbResult<usize> {
+        Ok(Thread::affinity_mask(self)?)
+    }
+
+    fn set_affinity_mask(&mut self, mask: usize) -> HcbResult<usize> {
+        Ok(Thread::set_affinity_mask(self, mask)?)
+    }
+}
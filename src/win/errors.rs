@@ -1,8 +1,11 @@
 use std::fmt;
 
 use winapi::shared::minwindef::DWORD;
+use winapi::shared::ntdef::NTSTATUS;
 use winapi::um::errhandlingapi::GetLastError;
 
+use win::ntdll::RtlNtStatusToDosError;
+
 #[derive(Debug, Copy, Clone, Fail)]
 pub struct Error(DWORD);
 
@@ -22,6 +25,11 @@ impl Error {
     pub fn last() -> Error {
         Error(unsafe { GetLastError() })
     }
+
+    /// Converts a failed native API status into the equivalent windows error.
+    pub fn from_ntstatus(status: NTSTATUS) -> Error {
+        Error(unsafe { RtlNtStatusToDosError(status) })
+    }
 }
 
 impl fmt::Display for Error {
@@ -13,6 +13,10 @@ pub struct Handle(winnt::HANDLE);
 
 impl Handle {
     /// Takes ownership of the handle.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be a valid handle which nothing else closes.
     pub unsafe fn new(handle: winnt::HANDLE) -> Handle {
         Handle(handle)
     }
@@ -25,6 +29,10 @@ impl Handle {
     }
 
     /// Duplicates the handle without taking ownership.
+    ///
+    /// # Safety
+    ///
+    /// `handle` must be a valid handle.
     pub unsafe fn duplicate_from(handle: winnt::HANDLE) -> WinResult<Handle> {
         let mut new_handle = null_mut();
         let res = wh::DuplicateHandle(
@@ -1,7 +1,10 @@
+mod backend;
 mod errors;
 mod handle;
+mod ntdll;
 mod process;
 
+pub use self::backend::WinBackend;
 pub use self::errors::{Error, WinResult};
 pub use self::handle::Handle;
 pub use self::process::{Process, Thread};
@@ -0,0 +1,70 @@
+//! Native API functions which winapi does not bind, or binds with the wrong types.
+
+#![allow(non_snake_case)]
+
+use winapi::shared::basetsd::{DWORD_PTR, ULONG_PTR};
+use winapi::shared::minwindef::BOOL;
+use winapi::shared::ntdef::{HANDLE, LONG, NTSTATUS, PULONG, PVOID, ULONG};
+
+/// `THREADINFOCLASS::ThreadBasicInformation`.
+pub const THREAD_BASIC_INFORMATION_CLASS: ULONG = 0;
+
+/// `PROCESSINFOCLASS::ProcessBasicInformation`.
+pub const PROCESS_BASIC_INFORMATION_CLASS: ULONG = 0;
+
+/// `PROCESSINFOCLASS::ProcessCommandLineInformation`, available from Windows 8.1.
+pub const PROCESS_COMMAND_LINE_INFORMATION: ULONG = 60;
+
+#[repr(C)]
+pub struct PROCESS_BASIC_INFORMATION {
+    pub ExitStatus: NTSTATUS,
+    pub PebBaseAddress: PVOID,
+    pub AffinityMask: ULONG_PTR,
+    pub BasePriority: LONG,
+    pub UniqueProcessId: ULONG_PTR,
+    pub InheritedFromUniqueProcessId: ULONG_PTR,
+}
+
+#[repr(C)]
+pub struct CLIENT_ID {
+    pub UniqueProcess: HANDLE,
+    pub UniqueThread: HANDLE,
+}
+
+#[repr(C)]
+pub struct THREAD_BASIC_INFORMATION {
+    pub ExitStatus: NTSTATUS,
+    pub TebBaseAddress: PVOID,
+    pub ClientId: CLIENT_ID,
+    pub AffinityMask: ULONG_PTR,
+    pub Priority: LONG,
+    pub BasePriority: LONG,
+}
+
+#[link(name = "ntdll")]
+extern "system" {
+    pub fn NtQueryInformationProcess(
+        ProcessHandle: HANDLE,
+        ProcessInformationClass: ULONG,
+        ProcessInformation: PVOID,
+        ProcessInformationLength: ULONG,
+        ReturnLength: PULONG,
+    ) -> NTSTATUS;
+
+    pub fn NtQueryInformationThread(
+        ThreadHandle: HANDLE,
+        ThreadInformationClass: ULONG,
+        ThreadInformation: PVOID,
+        ThreadInformationLength: ULONG,
+        ReturnLength: PULONG,
+    ) -> NTSTATUS;
+
+    pub fn RtlNtStatusToDosError(Status: NTSTATUS) -> ULONG;
+}
+
+#[link(name = "kernel32")]
+extern "system" {
+    /// winapi declares the mask as a `DWORD`, which leaves out CPUs 32 and up on 64-bit
+    /// Windows.
+    pub fn SetProcessAffinityMask(hProcess: HANDLE, dwProcessAffinityMask: DWORD_PTR) -> BOOL;
+}
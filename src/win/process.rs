@@ -2,12 +2,16 @@ use std::ffi::OsString;
 use std::mem;
 use std::os::windows::prelude::*;
 use std::path::PathBuf;
+use std::ptr;
+use std::slice;
 
 use winapi::shared::basetsd::{ULONG64, DWORD_PTR};
-use winapi::shared::minwindef::{DWORD, MAX_PATH};
+use winapi::shared::minwindef::{DWORD, FILETIME, MAX_PATH};
+use winapi::shared::ntdef::{NT_SUCCESS, ULONG, UNICODE_STRING};
 use winapi::um::handleapi::INVALID_HANDLE_VALUE;
-use winapi::um::processthreadsapi::{GetExitCodeProcess, GetProcessId, GetThreadId,
-                                    GetThreadIdealProcessorEx, OpenProcess, OpenThread,
+use winapi::um::processthreadsapi::{GetExitCodeProcess, GetProcessId, GetProcessIdOfThread,
+                                    GetProcessTimes, GetThreadId, GetThreadIdealProcessorEx,
+                                    OpenProcess, OpenThread, ProcessIdToSessionId,
                                     SetThreadIdealProcessor};
 use winapi::um::realtimeapiset::QueryThreadCycleTime;
 use winapi::um::tlhelp32::{CreateToolhelp32Snapshot, PROCESSENTRY32, Process32Next,
@@ -16,6 +20,10 @@ use winapi::um::winbase::{GetProcessAffinityMask, QueryFullProcessImageNameW,
                           SetThreadAffinityMask};
 use winapi::um::winnt::{PROCESSOR_NUMBER, PROCESS_ALL_ACCESS, THREAD_ALL_ACCESS, WCHAR};
 
+use win::ntdll::{NtQueryInformationProcess, NtQueryInformationThread, SetProcessAffinityMask,
+                 PROCESS_BASIC_INFORMATION, PROCESS_BASIC_INFORMATION_CLASS,
+                 PROCESS_COMMAND_LINE_INFORMATION, THREAD_BASIC_INFORMATION,
+                 THREAD_BASIC_INFORMATION_CLASS};
 use win::{self, Handle, WinResult};
 
 #[derive(Debug)]
@@ -85,6 +93,91 @@ impl Process {
         }
     }
 
+    /// Returns the command line the process was started with.
+    ///
+    /// Requires Windows 8.1 or later.
+    pub fn command_line(&self) -> WinResult<String> {
+        unsafe {
+            let mut size: ULONG = 0;
+            NtQueryInformationProcess(
+                self.handle.as_raw_handle(),
+                PROCESS_COMMAND_LINE_INFORMATION,
+                ptr::null_mut(),
+                0,
+                &mut size,
+            );
+            // A u64 buffer keeps the UNICODE_STRING header suitably aligned.
+            let mut buffer: Vec<u64> = vec![0; (size as usize).div_ceil(8)];
+            let status = NtQueryInformationProcess(
+                self.handle.as_raw_handle(),
+                PROCESS_COMMAND_LINE_INFORMATION,
+                buffer.as_mut_ptr() as *mut _,
+                (buffer.len() * 8) as ULONG,
+                &mut size,
+            );
+            if !NT_SUCCESS(status) {
+                return Err(win::Error::from_ntstatus(status));
+            }
+            let string = &*(buffer.as_ptr() as *const UNICODE_STRING);
+            let wide = slice::from_raw_parts(string.Buffer, string.Length as usize / 2);
+            Ok(OsString::from_wide(wide).to_string_lossy().into_owned())
+        }
+    }
+
+    /// Returns the id of the process which created this one.
+    ///
+    /// The parent may since have exited and its id been reused.
+    pub fn parent_id(&self) -> WinResult<u32> {
+        unsafe {
+            let mut info: PROCESS_BASIC_INFORMATION = mem::zeroed();
+            let status = NtQueryInformationProcess(
+                self.handle.as_raw_handle(),
+                PROCESS_BASIC_INFORMATION_CLASS,
+                &mut info as *mut PROCESS_BASIC_INFORMATION as *mut _,
+                mem::size_of::<PROCESS_BASIC_INFORMATION>() as ULONG,
+                ptr::null_mut(),
+            );
+            if !NT_SUCCESS(status) {
+                return Err(win::Error::from_ntstatus(status));
+            }
+            Ok(info.InheritedFromUniqueProcessId as u32)
+        }
+    }
+
+    /// Returns the id of the login session the process runs in, which is 0 for services.
+    pub fn session_id(&self) -> WinResult<u32> {
+        let mut session: DWORD = 0;
+        unsafe {
+            if ProcessIdToSessionId(self.id(), &mut session) == 0 {
+                Err(win::Error::last())
+            } else {
+                Ok(session)
+            }
+        }
+    }
+
+    /// Returns when the process was created, in 100 nanosecond intervals since 1601.
+    pub fn start_time(&self) -> WinResult<u64> {
+        unsafe {
+            let mut creation: FILETIME = mem::zeroed();
+            let mut exit: FILETIME = mem::zeroed();
+            let mut kernel: FILETIME = mem::zeroed();
+            let mut user: FILETIME = mem::zeroed();
+            let ret = GetProcessTimes(
+                self.handle.as_raw_handle(),
+                &mut creation,
+                &mut exit,
+                &mut kernel,
+                &mut user,
+            );
+            if ret == 0 {
+                Err(win::Error::last())
+            } else {
+                Ok(u64::from(creation.dwHighDateTime) << 32 | u64::from(creation.dwLowDateTime))
+            }
+        }
+    }
+
     /// Returns the unqualified name of the executable of the process.
     pub fn name(&self) -> WinResult<String> {
         Ok(self.path()?
@@ -112,6 +205,21 @@ impl Process {
         }
     }
 
+    /// Sets the affinity mask of the process. On success, returns the previous affinity mask.
+    ///
+    /// The threads of the process are rescheduled onto processors in the new mask, and any
+    /// thread affinity masks are reset to it.
+    pub fn set_affinity_mask(&mut self, mask: usize) -> WinResult<usize> {
+        let prev = self.affinity_mask()?;
+        unsafe {
+            if SetProcessAffinityMask(self.handle.as_raw_handle(), mask as DWORD_PTR) == 0 {
+                Err(win::Error::last())
+            } else {
+                Ok(prev)
+            }
+        }
+    }
+
     pub fn threads<'a>(&'a self) -> WinResult<impl Iterator<Item = Thread> + 'a> {
         unsafe {
             let snap = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0);
@@ -119,7 +227,7 @@ impl Process {
                 Err(win::Error::last())
             } else {
                 Ok(ThreadIter {
-                    process: &self,
+                    process: self,
                     snapshot: Handle::new(snap),
                 }.filter_map(Result::ok))
             }
@@ -133,7 +241,7 @@ impl Process {
                 Err(win::Error::last())
             } else {
                 Ok(ThreadIdIter {
-                    process: &self,
+                    process: self,
                     snapshot: Handle::new(snap),
                 })
             }
@@ -218,7 +326,7 @@ impl Thread {
     pub fn set_ideal_processor(&mut self, processor: u32) -> WinResult<u32> {
         unsafe {
             let ret = SetThreadIdealProcessor(self.handle.as_raw_handle(), processor as DWORD);
-            if ret == DWORD::max_value() {
+            if ret == DWORD::MAX {
                 Err(win::Error::last())
             } else {
                 Ok(ret)
@@ -226,6 +334,32 @@ impl Thread {
         }
     }
 
+    /// Returns the id of the process the thread belongs to.
+    pub fn process_id(&self) -> WinResult<u32> {
+        match unsafe { GetProcessIdOfThread(self.handle.as_raw_handle()) } {
+            0 => Err(win::Error::last()),
+            id => Ok(id),
+        }
+    }
+
+    /// Returns the affinity mask of the thread.
+    pub fn affinity_mask(&self) -> WinResult<usize> {
+        unsafe {
+            let mut info: THREAD_BASIC_INFORMATION = mem::zeroed();
+            let status = NtQueryInformationThread(
+                self.handle.as_raw_handle(),
+                THREAD_BASIC_INFORMATION_CLASS,
+                &mut info as *mut THREAD_BASIC_INFORMATION as *mut _,
+                mem::size_of::<THREAD_BASIC_INFORMATION>() as ULONG,
+                ptr::null_mut(),
+            );
+            if !NT_SUCCESS(status) {
+                return Err(win::Error::from_ntstatus(status));
+            }
+            Ok(info.AffinityMask as usize)
+        }
+    }
+
     /// Sets the affinity of the thread. On success, returns the previous affinity mask.
     ///
     /// A thread affinity mask is a bit vector in which each bit represents a logical processor